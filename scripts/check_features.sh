@@ -0,0 +1,17 @@
+#!/bin/bash -x
+
+cd "$(dirname "$0")/.."
+
+for FEATURES in \
+  "--all-features" \
+  "--no-default-features" \
+  "--no-default-features --features commands" \
+  "--no-default-features --features connection-info"
+do
+  cargo clippy --all-targets $FEATURES -- -D warnings
+  RESULT=$?
+  if [ $RESULT -ne 0 ]; then
+    echo "cargo clippy $FEATURES failed RESULT = $RESULT"
+    exit $RESULT
+  fi
+done
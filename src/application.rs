@@ -1,49 +1,194 @@
+mod builder;
+mod reload;
 mod server;
 
+pub use builder::{
+    BuilderCommandsService, PluginServerBuilder, ServerBuilder, ServerHandle, new_server_builder,
+};
+
+use axum::{
+    Router,
+    http::{StatusCode, header},
+    middleware,
+};
+
 use tower::ServiceBuilder;
 
 use tower_http::{
     ServiceBuilderExt,
     timeout::TimeoutLayer,
-    trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer},
+    trace::{DefaultOnResponse, TraceLayer},
 };
 
 use std::sync::Arc;
 
-use crate::{config, controller, service, utils};
+#[cfg(feature = "commands")]
+use std::process::ExitCode;
 
-pub async fn run(config_file: String) -> anyhow::Result<()> {
-    config::read_configuration(config_file).await?;
+#[cfg(feature = "commands")]
+use tracing::{info, warn};
 
-    let server_configuration = &config::instance().server_configuration;
+#[cfg(feature = "commands")]
+use crate::service::{
+    auth_service::Principal,
+    command_service::{CommandID, CommandsService, RunCommandError},
+};
 
-    let command_service = service::command_service::new_commands_service();
+use crate::{
+    config::{self, ConfigSources, SharedConfiguration},
+    service, utils,
+};
 
-    let connection_tracker_service = service::connection_service::new_connection_tracker_service();
+// Exit code of the self-test and run-command subcommands when a command did
+// not succeed.
+#[cfg(feature = "commands")]
+pub const COMMAND_FAILED_EXIT_CODE: u8 = 3;
 
-    let routes = controller::create_routes(
-        server_configuration,
-        command_service,
-        Arc::clone(&connection_tracker_service),
+pub fn version() -> anyhow::Result<()> {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&service::version_service::verison_info())?
     );
 
-    let routes = routes
-        // Add middleware to all routes
-        .layer(
-            ServiceBuilder::new()
-                // make sure to set request ids before the request reaches `TraceLayer`
-                .set_x_request_id(utils::request::CounterRequestId::default())
-                // log requests and responses
-                .layer(
-                    TraceLayer::new_for_http()
-                        .make_span_with(DefaultMakeSpan::new().include_headers(true))
-                        .on_response(DefaultOnResponse::new().include_headers(true)),
-                )
-                // propagate the header to the response before the response reaches `TraceLayer`
-                .propagate_x_request_id()
-                .layer(TimeoutLayer::new(server_configuration.request_timeout))
-                .into_inner(),
-        );
-
-    self::server::run(routes, server_configuration, connection_tracker_service).await
+    Ok(())
+}
+
+// JSON Schema of the config file, e.g. for editor validation.
+pub fn config_schema() -> anyhow::Result<()> {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&schemars::schema_for!(config::Configuration))?
+    );
+
+    Ok(())
+}
+
+// Validate the configuration like serve does and print it with defaults
+// filled in, or print each value with the layer it came from.
+pub async fn check_config(config_sources: ConfigSources, show_origin: bool) -> anyhow::Result<()> {
+    let configuration = config::read_configuration(&config_sources).await?;
+
+    #[cfg(feature = "commands")]
+    service::command_service::validate_command_configuration(&configuration.command_configuration)?;
+
+    service::auth_service::new_auth_service(configuration.auth_configuration.as_ref())?;
+
+    if show_origin {
+        let layered_configuration = config::read_layered_configuration(&config_sources).await?;
+        print!("{}", layered_configuration.origins_report(&configuration)?);
+    } else {
+        print!("{}", toml::to_string_pretty(&configuration)?);
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "commands")]
+pub async fn list_commands(config_sources: ConfigSources) -> anyhow::Result<()> {
+    let configuration = config::read_configuration(&config_sources).await?;
+
+    let command_service = service::command_service::new_commands_service(Arc::new(configuration))?;
+
+    let command_info_list = command_service.all_commands(false, &Principal::local());
+
+    println!("{}", serde_json::to_string_pretty(&command_info_list)?);
+
+    Ok(())
+}
+
+// Run a command once as an internal request would, without role checks.
+#[cfg(feature = "commands")]
+pub async fn run_command(
+    config_sources: ConfigSources,
+    command_id: String,
+) -> anyhow::Result<ExitCode> {
+    let configuration = config::read_configuration(&config_sources).await?;
+
+    let command_service = service::command_service::new_commands_service(Arc::new(configuration))?;
+
+    let run_command_dto = match command_service
+        .run_command(false, &Principal::local(), CommandID(command_id.clone()))
+        .await
+    {
+        Ok(run_command_dto) => run_command_dto,
+        Err(RunCommandError::CommandNotFound) => {
+            anyhow::bail!("command '{command_id}' not found")
+        }
+        Err(run_command_error) => {
+            anyhow::bail!("command '{command_id}' error {run_command_error:?}")
+        }
+    };
+
+    println!("{}", serde_json::to_string_pretty(&run_command_dto)?);
+
+    if !run_command_dto.succeeded() {
+        warn!(command_id, "command failed");
+        return Ok(ExitCode::from(COMMAND_FAILED_EXIT_CODE));
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+// Run every configured command once and print a json report.
+#[cfg(feature = "commands")]
+pub async fn self_test(config_sources: ConfigSources) -> anyhow::Result<ExitCode> {
+    let configuration = config::read_configuration(&config_sources).await?;
+
+    let command_service = service::command_service::new_commands_service(Arc::new(configuration))?;
+
+    let self_test_report = command_service.self_test().await;
+
+    println!("{}", serde_json::to_string_pretty(&self_test_report)?);
+
+    if !self_test_report.all_passed() {
+        warn!("self test failed");
+        return Ok(ExitCode::from(COMMAND_FAILED_EXIT_CODE));
+    }
+
+    info!("self test passed");
+
+    Ok(ExitCode::SUCCESS)
+}
+
+pub async fn run(config_sources: ConfigSources) -> anyhow::Result<()> {
+    let configuration = config::read_configuration(&config_sources).await?;
+
+    new_server_builder(configuration)
+        .reload_from(config_sources)
+        .start()
+        .await?
+        .wait()
+        .await
+}
+
+// The middleware stack of the server, outermost first.
+pub fn add_middleware(routes: Router, shared_configuration: &SharedConfiguration) -> Router {
+    let server_configuration = &shared_configuration.current().server_configuration;
+
+    routes.layer(
+        ServiceBuilder::new()
+            // make sure to set request ids before the request reaches `TraceLayer`
+            .map_request(utils::request::remove_request_id)
+            .set_x_request_id(utils::request::CounterRequestId::default())
+            // keep credentials out of the logged request headers
+            .sensitive_request_headers(Arc::from([header::AUTHORIZATION]))
+            // resolve the client behind trusted proxies before logging the request
+            .layer(middleware::from_fn_with_state(
+                shared_configuration.clone(),
+                utils::forwarded::resolve_client_info,
+            ))
+            // log requests and responses
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(utils::request::ClientMakeSpan)
+                    .on_response(DefaultOnResponse::new().include_headers(true)),
+            )
+            // propagate the header to the response before the response reaches `TraceLayer`
+            .propagate_x_request_id()
+            .layer(TimeoutLayer::with_status_code(
+                StatusCode::REQUEST_TIMEOUT,
+                server_configuration.request_timeout,
+            ))
+            .into_inner(),
+    )
 }
@@ -0,0 +1,304 @@
+use anyhow::Context;
+
+use axum::Router;
+
+use tokio::{
+    sync::watch,
+    task::{JoinHandle, JoinSet},
+};
+
+use tracing::info;
+
+use std::{net::SocketAddr, sync::Arc};
+
+#[cfg(feature = "commands")]
+use crate::service::command_service::CommandsService;
+
+use crate::{
+    config::{self, ConfigSources, Configuration, SharedConfiguration},
+    controller,
+    plugin::{Plugin, PluginContext},
+    service::{self, connection_service::ConnectionTrackerService},
+};
+
+#[cfg(feature = "commands")]
+type CommandsServiceFactory<C> =
+    Box<dyn FnOnce(Arc<Configuration>) -> anyhow::Result<Arc<C>> + Send>;
+
+// Builds without the commands feature have no commands service.
+#[cfg(not(feature = "commands"))]
+type CommandsServiceFactory<C> = std::marker::PhantomData<C>;
+
+// Bound on the commands service type of a builder, `()` in builds without the
+// commands feature.
+#[cfg(feature = "commands")]
+pub trait BuilderCommandsService: CommandsService {}
+
+#[cfg(feature = "commands")]
+impl<C: CommandsService> BuilderCommandsService for C {}
+
+#[cfg(not(feature = "commands"))]
+pub trait BuilderCommandsService: Send + Sync + 'static {}
+
+#[cfg(not(feature = "commands"))]
+impl BuilderCommandsService for () {}
+
+// Builds a server from a `Configuration` value, e.g. to embed it in another
+// binary or to run servers with different configurations in one process.
+//
+// Binaries using sandboxed commands must call
+// `service::command_service::exec_command_launcher_if_requested()` first
+// thing in main.
+pub struct ServerBuilder<C, T> {
+    configuration: Configuration,
+    config_sources: Option<ConfigSources>,
+    extra_routes: Router,
+    commands_service: CommandsServiceFactory<C>,
+    connection_tracker_service: Arc<T>,
+}
+
+// A builder after the first `plugin` call. Plugins depend on the service
+// types, so services can no longer be replaced.
+pub struct PluginServerBuilder<C, T> {
+    server_builder: ServerBuilder<C, T>,
+    plugins: Vec<Arc<dyn Plugin<C, T>>>,
+}
+
+pub fn new_server_builder(
+    configuration: Configuration,
+) -> ServerBuilder<impl BuilderCommandsService, impl ConnectionTrackerService> {
+    ServerBuilder {
+        configuration,
+        config_sources: None,
+        extra_routes: Router::new(),
+        #[cfg(feature = "commands")]
+        commands_service: Box::new(service::command_service::new_commands_service),
+        #[cfg(not(feature = "commands"))]
+        commands_service: std::marker::PhantomData::<()>,
+        connection_tracker_service: service::connection_service::new_connection_tracker_service(),
+    }
+}
+
+impl<C: BuilderCommandsService, T: ConnectionTrackerService> ServerBuilder<C, T> {
+    // Reload the configuration from these sources on SIGHUP or file changes.
+    pub fn reload_from(mut self, config_sources: ConfigSources) -> Self {
+        self.config_sources = Some(config_sources);
+        self
+    }
+
+    // Routes served under server_configuration.context next to the built-in
+    // ones. Paths must not overlap the built-in routes.
+    pub fn routes(mut self, routes: Router) -> Self {
+        self.extra_routes = self.extra_routes.merge(routes);
+        self
+    }
+
+    pub fn plugin(self, plugin: impl Plugin<C, T>) -> PluginServerBuilder<C, T> {
+        PluginServerBuilder {
+            server_builder: self,
+            plugins: Vec::new(),
+        }
+        .plugin(plugin)
+    }
+
+    // Replaces the commands service built from command_configuration.
+    #[cfg(feature = "commands")]
+    pub fn commands_service<C2: CommandsService>(
+        self,
+        commands_service: Arc<C2>,
+    ) -> ServerBuilder<C2, T> {
+        ServerBuilder {
+            configuration: self.configuration,
+            config_sources: self.config_sources,
+            extra_routes: self.extra_routes,
+            commands_service: Box::new(move |_| Ok(commands_service)),
+            connection_tracker_service: self.connection_tracker_service,
+        }
+    }
+
+    pub fn connection_tracker_service<T2: ConnectionTrackerService>(
+        self,
+        connection_tracker_service: Arc<T2>,
+    ) -> ServerBuilder<C, T2> {
+        ServerBuilder {
+            configuration: self.configuration,
+            config_sources: self.config_sources,
+            extra_routes: self.extra_routes,
+            commands_service: self.commands_service,
+            connection_tracker_service,
+        }
+    }
+
+    // Binds server_configuration.bind_address and starts accepting
+    // connections. Port 0 binds any free port, see `ServerHandle::local_addr`.
+    pub async fn start(self) -> anyhow::Result<ServerHandle> {
+        self.start_with_plugins(Vec::new()).await
+    }
+
+    async fn start_with_plugins(
+        self,
+        plugins: Vec<Arc<dyn Plugin<C, T>>>,
+    ) -> anyhow::Result<ServerHandle> {
+        config::check_features(&self.configuration)?;
+
+        config::check_server_configuration(&self.configuration.server_configuration)?;
+
+        let shared_configuration = SharedConfiguration::new(self.configuration);
+
+        let configuration = shared_configuration.current();
+
+        #[cfg(feature = "commands")]
+        let command_service = (self.commands_service)(Arc::clone(&configuration))?;
+
+        #[cfg(feature = "commands")]
+        command_service.start_schedules();
+
+        #[cfg(feature = "commands")]
+        let audit_service = service::audit_service::new_audit_service(
+            configuration.audit_log_configuration.as_ref(),
+        )
+        .await?;
+
+        let auth_service =
+            service::auth_service::new_auth_service(configuration.auth_configuration.as_ref())?;
+
+        let config_service = service::config_service::new_config_service(
+            shared_configuration.clone(),
+            self.config_sources.clone(),
+        );
+
+        let (shutdown_sender, shutdown_receiver) = watch::channel(());
+
+        if let Some(config_sources) = self.config_sources {
+            super::reload::start(
+                config_sources,
+                shared_configuration.clone(),
+                #[cfg(feature = "commands")]
+                Arc::clone(&command_service),
+                Arc::clone(&config_service),
+                shutdown_receiver.clone(),
+            )?;
+        }
+
+        let plugin_context = PluginContext {
+            shared_configuration: shared_configuration.clone(),
+            #[cfg(feature = "commands")]
+            commands_service: Arc::clone(&command_service),
+            #[cfg(not(feature = "commands"))]
+            _commands_service: std::marker::PhantomData,
+            connection_tracker_service: Arc::clone(&self.connection_tracker_service),
+        };
+
+        let mut plugin_tasks = JoinSet::new();
+
+        let mut extra_routes = self.extra_routes;
+
+        for plugin in &plugins {
+            info!(name = plugin.name(), "starting plugin");
+            extra_routes = extra_routes.merge(plugin.routes(&plugin_context));
+            plugin.start_tasks(&plugin_context, &mut plugin_tasks);
+        }
+
+        let plugin_service = service::plugin_service::new_plugin_service(plugins, extra_routes);
+
+        let routes = controller::create_routes(
+            &configuration.server_configuration,
+            #[cfg(feature = "commands")]
+            command_service,
+            #[cfg(feature = "connection-info")]
+            Arc::clone(&self.connection_tracker_service),
+            #[cfg(feature = "commands")]
+            audit_service,
+            auth_service,
+            config_service,
+            plugin_service,
+        );
+
+        let routes = super::add_middleware(routes, &shared_configuration);
+
+        let listener = super::server::create_listener(&configuration.server_configuration).await?;
+
+        let local_addr = listener
+            .local_addr()
+            .context("TCP server local_addr error")?;
+
+        let server_task = tokio::spawn(super::server::run(
+            listener,
+            routes,
+            shared_configuration,
+            self.connection_tracker_service,
+            shutdown_receiver,
+        ));
+
+        Ok(ServerHandle {
+            local_addr,
+            shutdown_sender,
+            server_task,
+            plugin_tasks,
+        })
+    }
+}
+
+impl<C: BuilderCommandsService, T: ConnectionTrackerService> PluginServerBuilder<C, T> {
+    pub fn reload_from(self, config_sources: ConfigSources) -> Self {
+        Self {
+            server_builder: self.server_builder.reload_from(config_sources),
+            ..self
+        }
+    }
+
+    pub fn routes(self, routes: Router) -> Self {
+        Self {
+            server_builder: self.server_builder.routes(routes),
+            ..self
+        }
+    }
+
+    pub fn plugin(mut self, plugin: impl Plugin<C, T>) -> Self {
+        self.plugins.push(Arc::new(plugin));
+        self
+    }
+
+    pub async fn start(self) -> anyhow::Result<ServerHandle> {
+        self.server_builder.start_with_plugins(self.plugins).await
+    }
+}
+
+// A running server. Dropping the handle shuts the server down like `shutdown`
+// without waiting for it.
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    shutdown_sender: watch::Sender<()>,
+    server_task: JoinHandle<anyhow::Result<()>>,
+    // aborted when dropped
+    plugin_tasks: JoinSet<()>,
+}
+
+impl ServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    // Stops accepting connections and waits for open connections to shut
+    // down gracefully.
+    pub async fn shutdown(self) -> anyhow::Result<()> {
+        info!(local_addr = ?self.local_addr, "shutting down server");
+
+        // fails only when the server already stopped
+        let _ = self.shutdown_sender.send(());
+
+        self.wait().await
+    }
+
+    // Waits until the server stops, which without `shutdown` is only on an
+    // error.
+    pub async fn wait(self) -> anyhow::Result<()> {
+        let result = self.server_task.await.context("server task join error")?;
+
+        // stops config reloading and plugin tasks after an error
+        drop(self.shutdown_sender);
+        drop(self.plugin_tasks);
+
+        result
+    }
+}
@@ -0,0 +1,180 @@
+use anyhow::Context;
+
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::watch,
+};
+
+use tracing::{debug, info, instrument, warn};
+
+use std::{path::PathBuf, sync::Arc, time::SystemTime};
+
+#[cfg(feature = "commands")]
+use crate::service::command_service::CommandsService;
+
+use crate::{
+    config::{self, ConfigSources, SharedConfiguration},
+    service::config_service::ConfigService,
+};
+
+// The config and overlay files and the files they include.
+fn files_to_watch(config_sources: &ConfigSources, included_files: &[PathBuf]) -> Vec<PathBuf> {
+    std::iter::once(&config_sources.config_file)
+        .chain(&config_sources.overlay_files)
+        .map(PathBuf::from)
+        .chain(included_files.iter().cloned())
+        .collect()
+}
+
+async fn modified_times(watched_files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    let mut modified_times = Vec::with_capacity(watched_files.len());
+
+    for path in watched_files {
+        modified_times.push(
+            tokio::fs::metadata(path)
+                .await
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+        );
+    }
+
+    modified_times
+}
+
+struct ReloadedConfiguration {
+    included_files: Vec<PathBuf>,
+    // changed settings that keep their in-effect values until a restart
+    restart_required: Vec<&'static str>,
+}
+
+async fn reload_configuration(
+    config_sources: &ConfigSources,
+    shared_configuration: &SharedConfiguration,
+    #[cfg(feature = "commands")] commands_service: &impl CommandsService,
+) -> anyhow::Result<ReloadedConfiguration> {
+    let (mut configuration, included_files) =
+        config::read_configuration_and_included_files(config_sources).await?;
+
+    let restart_required =
+        config::keep_restart_required_settings(&shared_configuration.current(), &mut configuration);
+    if !restart_required.is_empty() {
+        warn!(
+            ?restart_required,
+            "configuration changes need a restart to take effect"
+        );
+    }
+
+    let configuration = Arc::new(configuration);
+
+    #[cfg(feature = "commands")]
+    commands_service.reload(Arc::clone(&configuration))?;
+
+    shared_configuration.replace(configuration);
+
+    Ok(ReloadedConfiguration {
+        included_files,
+        restart_required,
+    })
+}
+
+// Reload on SIGHUP, and when config_watch_interval is set, on changes to the
+// modification times of the config, overlay or included files.
+pub fn start(
+    config_sources: ConfigSources,
+    shared_configuration: SharedConfiguration,
+    #[cfg(feature = "commands")] commands_service: Arc<impl CommandsService>,
+    config_service: Arc<impl ConfigService>,
+    shutdown: watch::Receiver<()>,
+) -> anyhow::Result<()> {
+    let sighup = signal(SignalKind::hangup()).context("error installing SIGHUP handler")?;
+
+    tokio::spawn(run(
+        config_sources,
+        shared_configuration,
+        sighup,
+        #[cfg(feature = "commands")]
+        commands_service,
+        config_service,
+        shutdown,
+    ));
+
+    Ok(())
+}
+
+#[instrument(name = "reload", skip_all)]
+async fn run(
+    config_sources: ConfigSources,
+    shared_configuration: SharedConfiguration,
+    mut sighup: tokio::signal::unix::Signal,
+    #[cfg(feature = "commands")] commands_service: Arc<impl CommandsService>,
+    config_service: Arc<impl ConfigService>,
+    mut shutdown: watch::Receiver<()>,
+) {
+    let watch_interval = shared_configuration
+        .current()
+        .server_configuration
+        .config_watch_interval;
+
+    debug!(?watch_interval, "begin run");
+
+    // the running configuration was read before, so read the included files
+    // again, a failure only leaves them unwatched until the next reload
+    let included_files = match watch_interval {
+        None => Vec::new(),
+        Some(_) => config::read_layered_configuration(&config_sources)
+            .await
+            .map(|layered_configuration| layered_configuration.included_files().to_vec())
+            .unwrap_or_default(),
+    };
+
+    let mut watched_files = files_to_watch(&config_sources, &included_files);
+
+    let mut last_modified = modified_times(&watched_files).await;
+
+    loop {
+        let watch_sleep = tokio::time::sleep(watch_interval.unwrap_or_default());
+
+        tokio::select! {
+            _ = shutdown.changed() => break,
+            _ = sighup.recv() => info!("got SIGHUP"),
+            _ = watch_sleep, if watch_interval.is_some() => {
+                if modified_times(&watched_files).await == last_modified {
+                    continue;
+                }
+                info!("config files modified");
+            }
+        }
+
+        last_modified = modified_times(&watched_files).await;
+
+        let result = match reload_configuration(
+            &config_sources,
+            &shared_configuration,
+            #[cfg(feature = "commands")]
+            commands_service.as_ref(),
+        )
+        .await
+        {
+            Ok(reloaded_configuration) => {
+                let reloaded_watched_files =
+                    files_to_watch(&config_sources, &reloaded_configuration.included_files);
+                if reloaded_watched_files != watched_files {
+                    watched_files = reloaded_watched_files;
+                    last_modified = modified_times(&watched_files).await;
+                }
+                Ok(reloaded_configuration.restart_required)
+            }
+            Err(error) => Err(error),
+        };
+
+        match &result {
+            Ok(_) => info!(?config_sources, "reloaded configuration"),
+            Err(error) => warn!(
+                ?error,
+                "configuration reload failed, keeping current configuration"
+            ),
+        }
+
+        config_service.record_reload(&result);
+    }
+}
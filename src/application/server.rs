@@ -11,7 +11,11 @@ use hyper_util::{
     server,
 };
 
-use tokio::net::{TcpListener, TcpStream};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::watch,
+    task::JoinSet,
+};
 
 use tower::Service;
 
@@ -20,35 +24,48 @@ use tracing::{debug, info, instrument, warn};
 use std::{convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
 
 use crate::{
-    config::ServerConfiguration,
+    config::{ServerConfiguration, SharedConfiguration},
     service::connection_service::{
-        ConnectionCounterMetricName, ConnectionGuard, ConnectionID, ConnectionTrackerService,
+        ClientConnectInfo, ConnectionCounterMetricName, ConnectionGuard, ConnectionTrackerService,
     },
 };
 
+// Accepts connections until `shutdown` changes or its sender is dropped, then
+// shuts down open connections gracefully and waits for them to close.
 pub async fn run(
+    listener: TcpListener,
     routes: Router,
-    server_configuration: &ServerConfiguration,
+    shared_configuration: SharedConfiguration,
     connection_tracker_service: Arc<impl ConnectionTrackerService>,
+    mut shutdown: watch::Receiver<()>,
 ) -> anyhow::Result<()> {
-    let listener = create_listener(server_configuration).await?;
+    let mut make_service = routes.into_make_service_with_connect_info::<ClientConnectInfo>();
 
-    let mut make_service = routes.into_make_service_with_connect_info::<ConnectionID>();
+    let mut connections = JoinSet::new();
 
-    let connection_timeout_durations = [
-        server_configuration.connection.max_lifetime,
-        server_configuration.connection.graceful_shutdown_timeout,
-    ];
-
-    debug!(
-        ?connection_timeout_durations,
-        server_configuration.connection.tcp_nodelay, "begin run"
-    );
+    debug!("begin run");
 
     loop {
-        let (tcp_stream, remote_addr) = listener.accept().await.context("listener accept error")?;
+        let (tcp_stream, remote_addr) = tokio::select! {
+            result = listener.accept() => result.context("listener accept error")?,
+            _ = shutdown.changed() => break,
+        };
 
-        if server_configuration.connection.tcp_nodelay {
+        // reap finished connections
+        while connections.try_join_next().is_some() {}
+
+        // read for each connection so reloaded settings apply to new connections
+        let connection_configuration = &shared_configuration
+            .current()
+            .server_configuration
+            .connection;
+
+        let connection_timeout_durations = [
+            connection_configuration.max_lifetime,
+            connection_configuration.graceful_shutdown_timeout,
+        ];
+
+        if connection_configuration.tcp_nodelay {
             debug!("calling tcp_stream.set_nodelay(true)");
             if let Err(e) = tcp_stream.set_nodelay(true) {
                 warn!("error setting tcp no delay {:?}", e);
@@ -62,7 +79,12 @@ pub async fn run(
             .add_connection()
             .await;
 
-        let tower_service = unwrap_infallible(make_service.call(connection_guard.id).await);
+        let client_connect_info = ClientConnectInfo {
+            connection_id: connection_guard.id,
+            remote_addr,
+        };
+
+        let tower_service = unwrap_infallible(make_service.call(client_connect_info).await);
 
         let connection = Connection {
             connection_guard,
@@ -70,13 +92,23 @@ pub async fn run(
             remote_addr,
             connection_timeout_durations,
             tower_service,
+            shutdown: shutdown.clone(),
         };
 
-        tokio::spawn(connection.run());
+        connections.spawn(connection.run());
     }
+
+    info!(
+        open_connections = connections.len(),
+        "shutting down, stopped accepting connections"
+    );
+
+    connections.join_all().await;
+
+    Ok(())
 }
 
-async fn create_listener(
+pub async fn create_listener(
     server_configuration: &ServerConfiguration,
 ) -> anyhow::Result<TcpListener> {
     let tcp_listener = TcpListener::bind(&server_configuration.bind_address)
@@ -100,7 +132,7 @@ async fn create_listener(
     Ok(tcp_listener)
 }
 
-type TowerService = AddExtension<Router, ConnectInfo<ConnectionID>>;
+type TowerService = AddExtension<Router, ConnectInfo<ClientConnectInfo>>;
 
 struct Connection {
     connection_guard: ConnectionGuard,
@@ -108,6 +140,7 @@ struct Connection {
     remote_addr: SocketAddr,
     connection_timeout_durations: [Duration; 2],
     tower_service: TowerService,
+    shutdown: watch::Receiver<()>,
 }
 
 impl Connection {
@@ -118,7 +151,7 @@ impl Connection {
             id = self.connection_guard.id.as_usize(),
         )
     )]
-    async fn run(self) {
+    async fn run(mut self) {
         debug!(?self.remote_addr, "begin Connection::run");
 
         let socket = TokioIo::new(self.tcp_stream);
@@ -149,6 +182,10 @@ impl Connection {
                     };
                     break;
                 }
+                _ = self.shutdown.changed(), if iter == 0 => {
+                    debug!("got server shutdown, calling conn.graceful_shutdown");
+                    hyper_conn.as_mut().graceful_shutdown();
+                }
                 _ = tokio::time::sleep(*sleep_duration) => {
                     debug!(iter, "got timeout_interval, calling conn.graceful_shutdown");
                     hyper_conn.as_mut().graceful_shutdown();
@@ -173,9 +210,9 @@ fn unwrap_infallible<T>(result: Result<T, Infallible>) -> T {
     result
 }
 
-impl connect_info::Connected<ConnectionID> for ConnectionID {
-    fn connect_info(id: ConnectionID) -> Self {
-        debug!(?id, "in connect_info::Connected");
-        id
+impl connect_info::Connected<ClientConnectInfo> for ClientConnectInfo {
+    fn connect_info(client_connect_info: ClientConnectInfo) -> Self {
+        debug!(?client_connect_info, "in connect_info::Connected");
+        client_connect_info
     }
 }
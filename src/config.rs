@@ -1,87 +1,808 @@
+mod include;
+pub mod layers;
+
 use anyhow::Context;
 
+use arc_swap::ArcSwap;
+
 use tracing::debug;
 
-use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+
+use serde::{Deserialize, Serialize, Serializer};
+
+use tokio::{fs::File, io::AsyncReadExt};
+
+use layers::{ConfigLayer, LayeredConfiguration};
+
+use std::{
+    collections::BTreeMap,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-use tokio::{fs::File, io::AsyncReadExt, sync::OnceCell};
+fn default_max_lifetime() -> Duration {
+    Duration::from_secs(5 * 60)
+}
 
-use std::time::Duration;
+fn default_graceful_shutdown_timeout() -> Duration {
+    Duration::from_secs(15)
+}
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct ServerConnectionConfiguration {
-    #[serde(with = "humantime_serde")]
+    // connections are gracefully shut down after this, default 5 minutes
+    #[serde(default = "default_max_lifetime", with = "humantime_serde")]
+    #[schemars(with = "String")]
     pub max_lifetime: Duration,
-    #[serde(with = "humantime_serde")]
+    // then closed after this, default 15 seconds
+    #[serde(
+        default = "default_graceful_shutdown_timeout",
+        with = "humantime_serde"
+    )]
+    #[schemars(with = "String")]
     pub graceful_shutdown_timeout: Duration,
+    #[serde(default)]
     pub tcp_nodelay: bool,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl Default for ServerConnectionConfiguration {
+    fn default() -> Self {
+        Self {
+            max_lifetime: default_max_lifetime(),
+            graceful_shutdown_timeout: default_graceful_shutdown_timeout(),
+            tcp_nodelay: false,
+        }
+    }
+}
+
+fn default_internal_networks() -> Vec<ipnet::IpNet> {
+    vec![
+        ipnet::Ipv4Net::new_assert(Ipv4Addr::new(127, 0, 0, 0), 8).into(),
+        ipnet::Ipv6Net::from(Ipv6Addr::LOCALHOST).into(),
+    ]
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkPolicyConfiguration {
+    // clients in these networks are internal, all others are external
+    #[serde(default = "default_internal_networks")]
+    #[schemars(with = "Vec<String>")]
+    pub internal_networks: Vec<ipnet::IpNet>,
+    // peers in these networks may set forwarding headers
+    #[serde(default)]
+    #[schemars(with = "Vec<String>")]
+    pub trusted_proxies: Vec<ipnet::IpNet>,
+    // the header trusted proxies set, the other one is ignored since proxies
+    // pass it through from clients
+    #[serde(default)]
+    pub forwarded_header: ForwardedHeader,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForwardedHeader {
+    // X-Forwarded-For with X-Forwarded-Proto and X-Forwarded-Host
+    #[default]
+    XForwardedFor,
+    // RFC 7239 Forwarded
+    Forwarded,
+}
+
+impl Default for NetworkPolicyConfiguration {
+    fn default() -> Self {
+        Self {
+            internal_networks: default_internal_networks(),
+            trusted_proxies: Vec::new(),
+            forwarded_header: ForwardedHeader::default(),
+        }
+    }
+}
+
+fn default_bind_address() -> String {
+    "[::]:8080".to_owned()
+}
+
+fn default_request_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_context() -> String {
+    "/api/v1".to_owned()
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct ServerConfiguration {
+    // default "[::]:8080"
+    #[serde(default = "default_bind_address")]
     pub bind_address: String,
-    #[serde(with = "humantime_serde")]
+    // default 10 seconds
+    #[serde(default = "default_request_timeout", with = "humantime_serde")]
+    #[schemars(with = "String")]
     pub request_timeout: Duration,
+    // path prefix of the api routes, default "/api/v1"
+    #[serde(default = "default_context")]
     pub context: String,
-    pub external_hosts: Vec<String>,
+    #[serde(default)]
+    pub network_policy: NetworkPolicyConfiguration,
+    #[serde(default)]
     pub connection: ServerConnectionConfiguration,
+    // also reload when the config file modification time changes
+    #[serde(default, with = "humantime_serde")]
+    #[schemars(with = "Option<String>")]
+    pub config_watch_interval: Option<Duration>,
+}
+
+impl Default for ServerConfiguration {
+    fn default() -> Self {
+        Self {
+            bind_address: default_bind_address(),
+            request_timeout: default_request_timeout(),
+            context: default_context(),
+            network_policy: NetworkPolicyConfiguration::default(),
+            connection: ServerConnectionConfiguration::default(),
+            config_watch_interval: None,
+        }
+    }
+}
+
+fn default_history_size() -> usize {
+    10
+}
+
+#[derive(Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandSchedule {
+    #[serde(default, with = "humantime_serde")]
+    #[schemars(with = "Option<String>")]
+    pub interval: Option<Duration>,
+    #[serde(default)]
+    #[schemars(with = "Option<String>")]
+    pub cron: Option<croner::Cron>,
+    #[serde(default = "default_history_size")]
+    pub history_size: usize,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandResourceLimits {
+    pub cpu_seconds: Option<u64>,
+    pub address_space_bytes: Option<u64>,
+    pub open_files: Option<u64>,
+    pub processes: Option<u64>,
+}
+
+impl CommandResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        self.cpu_seconds.is_none()
+            && self.address_space_bytes.is_none()
+            && self.open_files.is_none()
+            && self.processes.is_none()
+    }
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SandboxFilesystem {
+    #[serde(default)]
+    pub read_only: Vec<PathBuf>,
+    #[serde(default)]
+    pub read_write: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SandboxProfile {
+    #[serde(default)]
+    pub no_new_privs: bool,
+    #[serde(default)]
+    pub no_network: bool,
+    #[serde(default)]
+    pub filesystem: Option<SandboxFilesystem>,
+    #[serde(default)]
+    pub denied_syscalls: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandExecutionConfiguration {
+    #[serde(default)]
+    pub env_clear: bool,
+    #[serde(default)]
+    pub env_inherit: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+    #[serde(default)]
+    pub uid: Option<u32>,
+    #[serde(default)]
+    pub gid: Option<u32>,
+    #[serde(default)]
+    pub rlimits: CommandResourceLimits,
+    #[serde(default)]
+    pub sandbox_profile: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandKind {
+    // command is the path of an executable to run
+    #[default]
+    External,
+    // command is the name of a builtin implemented by the server
+    Builtin,
+    // steps are run one after another
+    Steps,
+    // steps are run concurrently with each stdout piped to the next stdin
+    Pipeline,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepFailureMode {
+    #[default]
+    FailFast,
+    RunAll,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandStep {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputParser {
+    Vmstat,
+    Df,
+    IpAddrJson,
+    KeyValue,
+    TableWhitespace,
+    Json,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct CommandInfo {
     pub id: String,
     #[serde(default)]
     pub internal_only: bool,
     pub description: String,
+    #[serde(default)]
+    pub kind: CommandKind,
+    #[serde(default)]
     pub command: String,
     #[serde(default)]
     pub args: Vec<String>,
+    #[serde(default)]
+    pub steps: Vec<CommandStep>,
+    #[serde(default)]
+    pub step_failure_mode: StepFailureMode,
+    #[serde(default)]
+    pub parser: Option<OutputParser>,
+    #[serde(default, with = "humantime_serde")]
+    #[schemars(with = "Option<String>")]
+    pub cache_ttl: Option<Duration>,
+    #[serde(default)]
+    pub max_concurrent: Option<usize>,
+    #[serde(default)]
+    pub schedule: Option<CommandSchedule>,
+    #[serde(default)]
+    pub execution: CommandExecutionConfiguration,
+    // roles allowed to see the command in listings, in addition to run_roles
+    #[serde(default)]
+    pub list_roles: Vec<String>,
+    // empty means any caller may run the command
+    #[serde(default)]
+    pub run_roles: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+fn default_max_concurrent_commands() -> usize {
+    10
+}
+
+fn default_semaphore_acquire_timeout() -> Duration {
+    Duration::from_millis(200)
+}
+
+fn default_self_test_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_proc_root() -> PathBuf {
+    PathBuf::from("/proc")
+}
+
+fn default_sys_root() -> PathBuf {
+    PathBuf::from("/sys")
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct CommandConfiguration {
+    // default 10
+    #[serde(default = "default_max_concurrent_commands")]
     pub max_concurrent_commands: usize,
-    #[serde(with = "humantime_serde")]
+    // unlimited by default
+    #[serde(default)]
+    pub max_queue_depth: Option<usize>,
+    // default 200 milliseconds
+    #[serde(
+        default = "default_semaphore_acquire_timeout",
+        with = "humantime_serde"
+    )]
+    #[schemars(with = "String")]
     pub semaphore_acquire_timeout: Duration,
+    // default 10 seconds
+    #[serde(default = "default_self_test_timeout", with = "humantime_serde")]
+    #[schemars(with = "String")]
+    pub self_test_timeout: Duration,
+    #[serde(default)]
+    pub sandbox_profiles: BTreeMap<String, SandboxProfile>,
+    // command library files, resolved when the config file is read
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<PathBuf>,
+    // ids of included commands to leave out, or in a file without include or
+    // commands, e.g. an overlay, ids of commands from earlier files
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove_commands: Vec<String>,
+    #[serde(default)]
     pub commands: Vec<CommandInfo>,
+    // read by builtin commands, e.g. host filesystems mounted in a container,
+    // default "/proc"
+    #[serde(default = "default_proc_root")]
+    pub proc_root: PathBuf,
+    // default "/sys"
+    #[serde(default = "default_sys_root")]
+    pub sys_root: PathBuf,
+}
+
+impl Default for CommandConfiguration {
+    fn default() -> Self {
+        Self {
+            max_concurrent_commands: default_max_concurrent_commands(),
+            max_queue_depth: None,
+            semaphore_acquire_timeout: default_semaphore_acquire_timeout(),
+            self_test_timeout: default_self_test_timeout(),
+            sandbox_profiles: BTreeMap::new(),
+            include: Vec::new(),
+            remove_commands: Vec::new(),
+            commands: Vec::new(),
+            proc_root: default_proc_root(),
+            sys_root: default_sys_root(),
+        }
+    }
+}
+
+fn default_audit_log_max_file_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_audit_log_max_rotated_files() -> usize {
+    5
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditLogConfiguration {
+    pub path: PathBuf,
+    #[serde(default = "default_audit_log_max_file_bytes")]
+    pub max_file_bytes: u64,
+    #[serde(default = "default_audit_log_max_rotated_files")]
+    pub max_rotated_files: usize,
+}
+
+// Secret values are never written out, e.g. by check-config or the
+// configuration endpoint.
+fn redact<T, S: Serializer>(_value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str("[redacted]")
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiTokenConfiguration {
+    pub name: String,
+    // hex encoded sha256 of the token
+    #[serde(serialize_with = "redact")]
+    pub token_sha256: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BasicAuthUserConfiguration {
+    pub username: String,
+    // argon2 PHC string
+    #[serde(serialize_with = "redact")]
+    pub password_hash: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfiguration {
+    #[serde(default)]
+    pub api_tokens: Vec<ApiTokenConfiguration>,
+    #[serde(default)]
+    pub users: Vec<BasicAuthUserConfiguration>,
+    // roles of requests without credentials
+    #[serde(default)]
+    pub anonymous_roles: Vec<String>,
+    // roles allowed to read the running configuration, audit log and status
+    // endpoints, which without auth_configuration only internal requests may
+    #[serde(default)]
+    pub admin_roles: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Configuration {
+    #[serde(default)]
     pub server_configuration: ServerConfiguration,
+    #[serde(default)]
     pub command_configuration: CommandConfiguration,
+    #[serde(default)]
+    pub audit_log_configuration: Option<AuditLogConfiguration>,
+    #[serde(default)]
+    pub auth_configuration: Option<AuthConfiguration>,
 }
 
-static CONFIGURATION_INSTANCE: OnceCell<Configuration> = OnceCell::const_new();
+// The layers a configuration is read from, in order: built-in defaults, the
+// config file, overlay files, `RUST_AXUM__` environment variables and
+// `--set key=value` overrides.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigSources {
+    pub config_file: String,
+    pub overlay_files: Vec<String>,
+    pub overrides: Vec<String>,
+}
 
-pub async fn read_configuration(config_file: String) -> anyhow::Result<()> {
-    debug!(config_file, "begin read_configuration");
+async fn read_file_string(path: impl AsRef<Path>) -> anyhow::Result<String> {
+    let path = path.as_ref();
 
-    let mut file = File::open(&config_file)
+    let mut file = File::open(path)
         .await
-        .with_context(|| format!("error opening '{config_file}'"))?;
+        .with_context(|| format!("error opening '{}'", path.display()))?;
 
     let mut file_contents = Vec::new();
 
     file.read_to_end(&mut file_contents)
         .await
-        .with_context(|| format!("error reading '{config_file}'"))?;
+        .with_context(|| format!("error reading '{}'", path.display()))?;
+
+    String::from_utf8(file_contents)
+        .with_context(|| format!("String::from_utf8 error reading '{}'", path.display()))
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
 
-    let file_contents_string = String::from_utf8(file_contents)
-        .with_context(|| format!("String::from_utf8 error reading '{config_file}'"))?;
+// The closest expected field for serde's "unknown field `x`, expected one of
+// `a`, `b`" errors.
+fn suggest_field(message: &str) -> Option<&str> {
+    let (_, rest) = message.split_once("unknown field `")?;
+    let (unknown_field, expected) = rest.split_once('`')?;
+
+    expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(|field| (edit_distance(unknown_field, field), field))
+        .filter(|(distance, _)| *distance <= (unknown_field.len() / 3).max(2))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, field)| field)
+}
+
+fn toml_error(error: ::toml::de::Error) -> anyhow::Error {
+    let message = error.to_string();
+
+    match suggest_field(&message) {
+        None => error.into(),
+        Some(field) => anyhow::anyhow!("{}\ndid you mean `{field}`?", message.trim_end()),
+    }
+}
+
+// The table of a config or overlay file and the files it includes.
+async fn read_toml_file(path: &str) -> anyhow::Result<(toml::Table, Vec<PathBuf>)> {
+    let file_contents = read_file_string(path).await?;
+
+    let mut table =
+        ::toml::from_str(&file_contents).with_context(|| format!("error parsing '{path}'"))?;
+
+    // every field has a default so each file must also be a valid configuration
+    // on its own, which reports errors with the line they are on
+    let configuration: Configuration = ::toml::from_str(&file_contents)
+        .map_err(toml_error)
+        .with_context(|| format!("error parsing '{path}'"))?;
+
+    let included_files = include::resolve_includes(
+        path,
+        &file_contents,
+        configuration.command_configuration,
+        &mut table,
+    )
+    .await?;
+
+    Ok((table, included_files))
+}
 
-    let configuration: Configuration = ::toml::from_str(&file_contents_string)
-        .with_context(|| format!("error unmarshalling '{config_file}'"))?;
+pub async fn read_layered_configuration(
+    config_sources: &ConfigSources,
+) -> anyhow::Result<LayeredConfiguration> {
+    debug!(?config_sources, "begin read_layered_configuration");
+
+    let mut layered_configuration = LayeredConfiguration::with_defaults()?;
+
+    let (table, included_files) = read_toml_file(&config_sources.config_file).await?;
+    layered_configuration.merge(
+        &ConfigLayer::File(config_sources.config_file.clone()),
+        table,
+    );
+    layered_configuration.add_included_files(included_files);
+
+    for overlay_file in &config_sources.overlay_files {
+        let (table, included_files) = read_toml_file(overlay_file).await?;
+        layered_configuration.merge(&ConfigLayer::Overlay(overlay_file.clone()), table);
+        layered_configuration.add_included_files(included_files);
+    }
+
+    layered_configuration.merge_environment(std::env::vars_os())?;
+
+    for set in &config_sources.overrides {
+        layered_configuration.merge_command_line(set)?;
+    }
+
+    layered_configuration.remove_commands()?;
+
+    Ok(layered_configuration)
+}
+
+pub async fn read_configuration(config_sources: &ConfigSources) -> anyhow::Result<Configuration> {
+    let (configuration, _) = read_configuration_and_included_files(config_sources).await?;
+
+    Ok(configuration)
+}
+
+// Also returns the command library files included by the config and overlay
+// files, which are watched for changes like them.
+pub async fn read_configuration_and_included_files(
+    config_sources: &ConfigSources,
+) -> anyhow::Result<(Configuration, Vec<PathBuf>)> {
+    let layered_configuration = read_layered_configuration(config_sources).await?;
+
+    let included_files = layered_configuration.included_files().to_vec();
+
+    let configuration: Configuration = ::toml::Value::Table(layered_configuration.into_table())
+        .try_into()
+        .map_err(toml_error)
+        .with_context(|| format!("error unmarshalling configuration {config_sources:?}"))?;
 
     debug!(?configuration, "read configuration");
 
-    CONFIGURATION_INSTANCE
-        .set(configuration)
-        .context("CONFIGURATION_INSTANCE.set error")?;
+    check_features(&configuration)?;
+
+    check_server_configuration(&configuration.server_configuration)?;
+
+    Ok((configuration, included_files))
+}
+
+// The current configuration of a server, shared by the services and
+// middleware that read it per request and replaced on reload. Requests and
+// commands in flight keep the `Arc` of the configuration they started with,
+// which is dropped when the last of them completes.
+#[derive(Clone)]
+pub struct SharedConfiguration(Arc<ArcSwap<Configuration>>);
+
+impl SharedConfiguration {
+    pub fn new(configuration: Configuration) -> Self {
+        Self(Arc::new(ArcSwap::from_pointee(configuration)))
+    }
+
+    pub fn current(&self) -> Arc<Configuration> {
+        self.0.load_full()
+    }
+
+    // Makes a reloaded configuration the current one.
+    pub fn replace(&self, configuration: Arc<Configuration>) {
+        self.0.store(configuration);
+    }
+}
+
+// Rejects settings of subsystems left out of the build by cargo features.
+pub fn check_features(configuration: &Configuration) -> anyhow::Result<()> {
+    if cfg!(not(feature = "commands")) {
+        let command_configuration = &configuration.command_configuration;
+
+        if !command_configuration.commands.is_empty()
+            || !command_configuration.include.is_empty()
+            || !command_configuration.sandbox_profiles.is_empty()
+        {
+            anyhow::bail!(
+                "command_configuration has commands, but the commands feature is disabled"
+            );
+        }
+
+        if configuration.audit_log_configuration.is_some() {
+            anyhow::bail!("audit_log_configuration is set, but the commands feature is disabled");
+        }
+    }
+
+    Ok(())
+}
+
+// Rejects server settings that deserialize but cannot work.
+pub fn check_server_configuration(
+    server_configuration: &ServerConfiguration,
+) -> anyhow::Result<()> {
+    if server_configuration.config_watch_interval == Some(Duration::ZERO) {
+        anyhow::bail!("server_configuration has config_watch_interval = 0");
+    }
 
     Ok(())
 }
 
-pub fn instance() -> &'static Configuration {
-    CONFIGURATION_INSTANCE.get().unwrap()
+// Settings that are only applied at startup, changing them needs a restart.
+fn restart_required_changes(
+    current: &Configuration,
+    reloaded: &Configuration,
+) -> Vec<&'static str> {
+    let current_server = &current.server_configuration;
+    let reloaded_server = &reloaded.server_configuration;
+
+    [
+        (
+            "server_configuration.bind_address",
+            current_server.bind_address != reloaded_server.bind_address,
+        ),
+        (
+            "server_configuration.request_timeout",
+            current_server.request_timeout != reloaded_server.request_timeout,
+        ),
+        (
+            "server_configuration.context",
+            current_server.context != reloaded_server.context,
+        ),
+        (
+            "server_configuration.config_watch_interval",
+            current_server.config_watch_interval != reloaded_server.config_watch_interval,
+        ),
+        (
+            "audit_log_configuration",
+            current.audit_log_configuration != reloaded.audit_log_configuration,
+        ),
+        (
+            "auth_configuration",
+            current.auth_configuration != reloaded.auth_configuration,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(name, changed)| changed.then_some(name))
+    .collect()
+}
+
+// Keeps the in-effect values of settings that need a restart in a reloaded
+// configuration, so it shows what is running and later reloads compare against
+// it. Returns the settings whose changes wait for a restart.
+pub fn keep_restart_required_settings(
+    current: &Configuration,
+    reloaded: &mut Configuration,
+) -> Vec<&'static str> {
+    let restart_required = restart_required_changes(current, reloaded);
+
+    let current_server = &current.server_configuration;
+    let reloaded_server = &mut reloaded.server_configuration;
+
+    reloaded_server
+        .bind_address
+        .clone_from(&current_server.bind_address);
+    reloaded_server.request_timeout = current_server.request_timeout;
+    reloaded_server.context.clone_from(&current_server.context);
+    reloaded_server.config_watch_interval = current_server.config_watch_interval;
+    reloaded
+        .audit_log_configuration
+        .clone_from(&current.audit_log_configuration);
+    reloaded
+        .auth_configuration
+        .clone_from(&current.auth_configuration);
+
+    restart_required
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distances() {
+        let cases = [
+            ("", "", 0),
+            ("bind_address", "bind_address", 0),
+            ("bind_adress", "bind_address", 1),
+            ("contxet", "context", 2),
+            ("", "abc", 3),
+            ("kitten", "sitting", 3),
+        ];
+
+        for (a, b, expected) in cases {
+            assert_eq!(edit_distance(a, b), expected, "{a} {b}");
+            assert_eq!(edit_distance(b, a), expected, "{b} {a}");
+        }
+    }
+
+    #[test]
+    fn suggest_fields() {
+        let cases = [
+            (
+                "unknown field `bind_adress`, expected one of `bind_address`, `request_timeout`, `context`",
+                Some("bind_address"),
+            ),
+            (
+                "unknown field `contxt`, expected `context`",
+                Some("context"),
+            ),
+            (
+                "unknown field `timeout`, expected one of `bind_address`, `request_timeout`",
+                None,
+            ),
+            ("unknown field `x`, there are no fields", None),
+            ("invalid type: string \"a\", expected usize", None),
+        ];
+
+        for (message, expected) in cases {
+            assert_eq!(suggest_field(message), expected, "{message}");
+        }
+    }
+
+    #[test]
+    fn toml_error_suggests_field() {
+        let cases = [
+            (
+                "[server_configuration]\nbind_adress = \"[::1]:8080\"\n",
+                Some("bind_address"),
+            ),
+            (
+                "[command_configuration]\nmax_concurent_commands = 1\n",
+                Some("max_concurrent_commands"),
+            ),
+            ("[server_configuration]\nbind_address = 1\n", None),
+        ];
+
+        for (contents, expected) in cases {
+            let error = ::toml::from_str::<Configuration>(contents).unwrap_err();
+
+            let message = format!("{:#}", toml_error(error));
+
+            match expected {
+                Some(field) => assert!(
+                    message.contains(&format!("did you mean `{field}`?")),
+                    "{message}"
+                ),
+                None => assert!(!message.contains("did you mean"), "{message}"),
+            }
+        }
+    }
 }
@@ -0,0 +1,456 @@
+use anyhow::Context;
+
+use serde::Deserialize;
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use super::{CommandConfiguration, CommandInfo, read_file_string, toml_error};
+
+// An included command library file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CommandLibrary {
+    // paths relative to the including file
+    #[serde(default)]
+    include: Vec<PathBuf>,
+    // added after included commands, replacing included commands with the
+    // same id, but not each other
+    #[serde(default)]
+    commands: Vec<CommandInfo>,
+    // ids of included commands to leave out
+    #[serde(default)]
+    remove_commands: Vec<String>,
+}
+
+// The ids of the commands of a file with their position, read separately
+// because CommandInfo has no spans.
+#[derive(Default, Deserialize)]
+struct CommandIds {
+    #[serde(default)]
+    commands: Vec<CommandId>,
+}
+
+#[derive(Deserialize)]
+struct CommandId {
+    id: toml::Spanned<String>,
+}
+
+#[derive(Default, Deserialize)]
+struct ConfigFileCommandIds {
+    #[serde(default)]
+    command_configuration: CommandIds,
+}
+
+fn line_number(file_contents: &str, offset: usize) -> usize {
+    file_contents[..offset.min(file_contents.len())]
+        .matches('\n')
+        .count()
+        + 1
+}
+
+// Commands of one file must have distinct ids, replacing is only for
+// included commands.
+fn check_duplicate_ids(
+    file: &Path,
+    file_contents: &str,
+    command_ids: &CommandIds,
+) -> anyhow::Result<()> {
+    let mut id_to_line = HashMap::new();
+
+    for command_id in &command_ids.commands {
+        let line = line_number(file_contents, command_id.id.span().start);
+
+        if let Some(previous_line) = id_to_line.insert(command_id.id.get_ref(), line) {
+            anyhow::bail!(
+                "duplicate command id '{}' in '{}' line {previous_line} and line {line}",
+                command_id.id.get_ref(),
+                file.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn upsert_command(commands: &mut Vec<CommandInfo>, command_info: CommandInfo) {
+    match commands
+        .iter_mut()
+        .find(|existing| existing.id == command_info.id)
+    {
+        Some(existing) => *existing = command_info,
+        None => commands.push(command_info),
+    }
+}
+
+fn display_include_stack(include_stack: &[PathBuf], include_path: &Path) -> String {
+    include_stack
+        .iter()
+        .map(|path| path.display().to_string())
+        .chain(std::iter::once(include_path.display().to_string()))
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+async fn resolve_commands(
+    file: &Path,
+    command_library: CommandLibrary,
+    include_stack: &mut Vec<PathBuf>,
+    included_files: &mut Vec<PathBuf>,
+) -> anyhow::Result<Vec<CommandInfo>> {
+    let directory = file.parent().unwrap_or(Path::new("."));
+
+    let mut commands = Vec::new();
+
+    // the include each included command came from
+    let mut id_to_include = HashMap::new();
+
+    for include in command_library.include {
+        let include_path = tokio::fs::canonicalize(directory.join(&include))
+            .await
+            .with_context(|| {
+                format!(
+                    "error resolving include '{}' in '{}'",
+                    include.display(),
+                    file.display()
+                )
+            })?;
+
+        if include_stack.contains(&include_path) {
+            anyhow::bail!(
+                "include cycle {}",
+                display_include_stack(include_stack, &include_path)
+            );
+        }
+
+        if !included_files.contains(&include_path) {
+            included_files.push(include_path.clone());
+        }
+
+        let included_contents = read_file_string(&include_path).await?;
+
+        let included_library: CommandLibrary = ::toml::from_str(&included_contents)
+            .map_err(toml_error)
+            .with_context(|| format!("error parsing '{}'", include_path.display()))?;
+
+        let included_ids: CommandIds = ::toml::from_str(&included_contents)
+            .with_context(|| format!("error parsing '{}'", include_path.display()))?;
+        check_duplicate_ids(&include_path, &included_contents, &included_ids)?;
+
+        include_stack.push(include_path.clone());
+        let included_commands = Box::pin(resolve_commands(
+            &include_path,
+            included_library,
+            include_stack,
+            included_files,
+        ))
+        .await?;
+        include_stack.pop();
+
+        for command_info in included_commands {
+            if let Some(other_include) =
+                id_to_include.insert(command_info.id.clone(), include_path.clone())
+            {
+                anyhow::bail!(
+                    "command id '{}' in '{}' is included from both '{}' and '{}'",
+                    command_info.id,
+                    file.display(),
+                    other_include.display(),
+                    include_path.display()
+                );
+            }
+            commands.push(command_info);
+        }
+    }
+
+    for command_info in command_library.commands {
+        upsert_command(&mut commands, command_info);
+    }
+
+    for id in command_library.remove_commands {
+        if !commands.iter().any(|command_info| command_info.id == id) {
+            anyhow::bail!(
+                "remove_commands id '{id}' in '{}' is not an included command",
+                file.display()
+            );
+        }
+        commands.retain(|command_info| command_info.id != id);
+    }
+
+    Ok(commands)
+}
+
+// Replaces `command_configuration` include and remove_commands in the table of
+// a config file with the resolved command list, and returns the included
+// files.
+//
+// remove_commands in a file without include or commands is left in the table
+// and removes commands of earlier layers, see
+// `LayeredConfiguration::remove_commands`.
+pub async fn resolve_includes(
+    config_file: &str,
+    file_contents: &str,
+    command_configuration: CommandConfiguration,
+    table: &mut toml::Table,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let config_file_ids: ConfigFileCommandIds = ::toml::from_str(file_contents)
+        .with_context(|| format!("error parsing '{config_file}'"))?;
+    check_duplicate_ids(
+        Path::new(config_file),
+        file_contents,
+        &config_file_ids.command_configuration,
+    )?;
+
+    let command_library = CommandLibrary {
+        include: command_configuration.include,
+        commands: command_configuration.commands,
+        remove_commands: command_configuration.remove_commands,
+    };
+
+    let has_commands = table
+        .get("command_configuration")
+        .and_then(toml::Value::as_table)
+        .is_some_and(|command_configuration| command_configuration.contains_key("commands"));
+
+    if command_library.include.is_empty()
+        && (command_library.remove_commands.is_empty() || !has_commands)
+    {
+        return Ok(Vec::new());
+    }
+
+    let config_file_path = tokio::fs::canonicalize(config_file)
+        .await
+        .with_context(|| format!("error resolving '{config_file}'"))?;
+
+    let mut include_stack = vec![config_file_path.clone()];
+
+    let mut included_files = Vec::new();
+
+    let commands = resolve_commands(
+        &config_file_path,
+        command_library,
+        &mut include_stack,
+        &mut included_files,
+    )
+    .await?;
+
+    let Some(toml::Value::Table(command_configuration)) = table.get_mut("command_configuration")
+    else {
+        anyhow::bail!("'{config_file}' command_configuration must be a table");
+    };
+
+    command_configuration.remove("include");
+    command_configuration.remove("remove_commands");
+    command_configuration.insert(
+        "commands".to_owned(),
+        toml::Value::try_from(commands).context("error serializing included commands")?,
+    );
+
+    Ok(included_files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Configuration;
+
+    // Config and library files under the temp dir, removed when dropped.
+    struct IncludeTree {
+        root: PathBuf,
+    }
+
+    impl IncludeTree {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir()
+                .join(format!("rust-axum-include-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir_all(&root).unwrap();
+            Self { root }
+        }
+
+        fn write(&self, path: &str, contents: &str) {
+            let path = self.root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        // The resolved command ids and descriptions of a config file.
+        async fn resolve(&self, path: &str) -> anyhow::Result<Vec<(String, String)>> {
+            let path = self.root.join(path).display().to_string();
+            let file_contents = std::fs::read_to_string(&path).unwrap();
+
+            let mut table: toml::Table = file_contents.parse().unwrap();
+            let configuration: Configuration = ::toml::from_str(&file_contents).unwrap();
+
+            resolve_includes(
+                &path,
+                &file_contents,
+                configuration.command_configuration,
+                &mut table,
+            )
+            .await?;
+
+            let commands = table["command_configuration"]["commands"]
+                .as_array()
+                .unwrap();
+
+            Ok(commands
+                .iter()
+                .map(|command| {
+                    (
+                        command["id"].as_str().unwrap().to_owned(),
+                        command["description"].as_str().unwrap().to_owned(),
+                    )
+                })
+                .collect())
+        }
+    }
+
+    impl Drop for IncludeTree {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn command(id: &str, description: &str) -> String {
+        format!(r#"{{ id = "{id}", description = "{description}", command = "/bin/true" }}"#)
+    }
+
+    fn include_tree() -> IncludeTree {
+        let tree = IncludeTree::new("tree");
+
+        tree.write(
+            "lib/common.toml",
+            &format!(
+                "include = [\"nested/disk.toml\"]\ncommands = [\n    {},\n    {},\n]\n",
+                command("a", "common a"),
+                command("b", "common b"),
+            ),
+        );
+        tree.write(
+            "lib/nested/disk.toml",
+            &format!("commands = [{}]\n", command("d", "disk d")),
+        );
+        tree.write(
+            "lib/other.toml",
+            &format!("commands = [{}]\n", command("a", "other a")),
+        );
+        tree.write(
+            "lib/duplicate.toml",
+            &format!(
+                "commands = [\n    {},\n    {},\n]\n",
+                command("x", "first"),
+                command("x", "second"),
+            ),
+        );
+        tree.write("cycle/first.toml", "include = [\"second.toml\"]\n");
+        tree.write("cycle/second.toml", "include = [\"../cycle/first.toml\"]\n");
+
+        tree
+    }
+
+    fn config_file(include: &[&str], commands: &[String], remove_commands: &[&str]) -> String {
+        format!(
+            "[command_configuration]\ninclude = {include:?}\ncommands = [\n{}]\nremove_commands = {remove_commands:?}\n",
+            commands
+                .iter()
+                .map(|command| format!("    {command},\n"))
+                .collect::<String>()
+        )
+    }
+
+    #[tokio::test]
+    async fn resolves_includes() {
+        let tree = include_tree();
+
+        let cases = [
+            (
+                config_file(&["lib/common.toml"], &[], &[]),
+                vec![("d", "disk d"), ("a", "common a"), ("b", "common b")],
+            ),
+            // the file's own commands replace included ones
+            (
+                config_file(
+                    &["lib/common.toml"],
+                    &[command("a", "own a"), command("c", "own c")],
+                    &["d"],
+                ),
+                vec![("a", "own a"), ("b", "common b"), ("c", "own c")],
+            ),
+            // left as is without include or remove_commands
+            (
+                config_file(&[], &[command("c", "own c")], &[]),
+                vec![("c", "own c")],
+            ),
+        ];
+
+        for (contents, expected) in cases {
+            tree.write("config.toml", &contents);
+
+            let commands = tree.resolve("config.toml").await.unwrap();
+
+            let expected: Vec<(String, String)> = expected
+                .into_iter()
+                .map(|(id, description)| (id.to_owned(), description.to_owned()))
+                .collect();
+            assert_eq!(commands, expected, "{contents}");
+        }
+    }
+
+    #[tokio::test]
+    async fn include_errors() {
+        let tree = include_tree();
+
+        let cases = [
+            (
+                config_file(&["cycle/first.toml"], &[], &[]),
+                vec!["include cycle", "first.toml -> ", "second.toml -> "],
+            ),
+            (
+                config_file(&["lib/missing.toml"], &[], &[]),
+                vec!["error resolving include 'lib/missing.toml'"],
+            ),
+            (
+                config_file(
+                    &["lib/common.toml"],
+                    &[command("x", "first"), command("x", "second")],
+                    &[],
+                ),
+                vec!["duplicate command id 'x'", "config.toml' line 4 and line 5"],
+            ),
+            (
+                config_file(&["lib/duplicate.toml"], &[], &[]),
+                vec![
+                    "duplicate command id 'x'",
+                    "duplicate.toml' line 2 and line 3",
+                ],
+            ),
+            (
+                config_file(&["lib/common.toml", "lib/other.toml"], &[], &[]),
+                vec![
+                    "command id 'a'",
+                    "included from both",
+                    "common.toml",
+                    "other.toml",
+                ],
+            ),
+            (
+                config_file(&["lib/common.toml"], &[], &["unknown"]),
+                vec!["remove_commands id 'unknown'", "is not an included command"],
+            ),
+        ];
+
+        for (contents, expected_messages) in cases {
+            tree.write("config.toml", &contents);
+
+            let error = format!("{:#}", tree.resolve("config.toml").await.unwrap_err());
+
+            for expected_message in expected_messages {
+                assert!(error.contains(expected_message), "{error}");
+            }
+        }
+    }
+}
@@ -0,0 +1,631 @@
+use anyhow::Context;
+
+use tracing::warn;
+
+use std::{collections::BTreeMap, ffi::OsString, fmt, path::PathBuf};
+
+use super::Configuration;
+
+const ENVIRONMENT_PREFIX: &str = "RUST_AXUM__";
+
+const ENVIRONMENT_SEPARATOR: &str = "__";
+
+// Where a configuration value came from, later layers win.
+#[derive(Clone, Debug)]
+pub enum ConfigLayer {
+    Defaults,
+    File(String),
+    Overlay(String),
+    Environment(String),
+    CommandLine(String),
+}
+
+impl fmt::Display for ConfigLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Defaults => write!(f, "defaults"),
+            Self::File(path) => write!(f, "file {path}"),
+            Self::Overlay(path) => write!(f, "overlay {path}"),
+            Self::Environment(name) => write!(f, "env {name}"),
+            Self::CommandLine(set) => write!(f, "--set {set}"),
+        }
+    }
+}
+
+// Override values are toml values, e.g. `10`, `true` or `["a", "b"]`. Anything
+// that does not parse is used as a string.
+fn parse_value(value: &str) -> toml::Value {
+    format!("value = {value}")
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_owned()))
+}
+
+fn nested_table(path: &[String], value: toml::Value) -> toml::Table {
+    let mut table = toml::Table::new();
+
+    match path {
+        [] => {}
+        [key] => {
+            table.insert(key.clone(), value);
+        }
+        [key, rest @ ..] => {
+            table.insert(key.clone(), toml::Value::Table(nested_table(rest, value)));
+        }
+    }
+
+    table
+}
+
+// The merged toml of all layers and the layer of each leaf value. Arrays are
+// leaves and are replaced as a whole.
+#[derive(Default)]
+pub struct LayeredConfiguration {
+    table: toml::Table,
+    origins: BTreeMap<String, ConfigLayer>,
+    // command library files included by the config and overlay files
+    included_files: Vec<PathBuf>,
+}
+
+impl LayeredConfiguration {
+    pub fn with_defaults() -> anyhow::Result<Self> {
+        let mut layered_configuration = Self::default();
+
+        let defaults = toml::Table::try_from(Configuration::default())
+            .context("error serializing default configuration")?;
+
+        layered_configuration.merge(&ConfigLayer::Defaults, defaults);
+
+        Ok(layered_configuration)
+    }
+
+    pub fn merge(&mut self, layer: &ConfigLayer, table: toml::Table) {
+        merge_table(&mut self.table, &mut self.origins, "", layer, table);
+    }
+
+    // `RUST_AXUM__SERVER_CONFIGURATION__BIND_ADDRESS=...` sets
+    // `server_configuration.bind_address`. Other variables are skipped without
+    // requiring them to be UTF-8.
+    pub fn merge_environment(
+        &mut self,
+        variables: impl IntoIterator<Item = (OsString, OsString)>,
+    ) -> anyhow::Result<()> {
+        let mut variables: Vec<(String, String)> = variables
+            .into_iter()
+            .filter(|(name, _)| {
+                name.as_encoded_bytes()
+                    .starts_with(ENVIRONMENT_PREFIX.as_bytes())
+            })
+            .map(|(name, value)| {
+                let display_name = name.to_string_lossy().into_owned();
+                match (name.into_string(), value.into_string()) {
+                    (Ok(name), Ok(value)) => Ok((name, value)),
+                    _ => anyhow::bail!("environment variable '{display_name}' is not valid UTF-8"),
+                }
+            })
+            .collect::<anyhow::Result<_>>()?;
+        variables.sort();
+
+        for (name, value) in variables {
+            let path: Vec<String> = name[ENVIRONMENT_PREFIX.len()..]
+                .split(ENVIRONMENT_SEPARATOR)
+                .map(str::to_ascii_lowercase)
+                .collect();
+
+            if path.iter().any(String::is_empty) {
+                warn!(name, "ignoring environment variable with an empty key");
+                continue;
+            }
+
+            self.merge(
+                &ConfigLayer::Environment(name.clone()),
+                nested_table(&path, parse_value(&value)),
+            );
+        }
+
+        Ok(())
+    }
+
+    // `--set server_configuration.bind_address=[::1]:8080`
+    pub fn merge_command_line(&mut self, set: &str) -> anyhow::Result<()> {
+        let (key, value) = set
+            .split_once('=')
+            .with_context(|| format!("--set '{set}' must be key=value"))?;
+
+        let path: Vec<String> = key.trim().split('.').map(str::to_owned).collect();
+        if path.iter().any(String::is_empty) {
+            anyhow::bail!("--set '{set}' has an empty key");
+        }
+
+        self.merge(
+            &ConfigLayer::CommandLine(set.to_owned()),
+            nested_table(&path, parse_value(value.trim())),
+        );
+
+        Ok(())
+    }
+
+    pub fn add_included_files(&mut self, included_files: Vec<PathBuf>) {
+        for included_file in included_files {
+            if !self.included_files.contains(&included_file) {
+                self.included_files.push(included_file);
+            }
+        }
+    }
+
+    pub fn included_files(&self) -> &[PathBuf] {
+        &self.included_files
+    }
+
+    // Applies a `command_configuration.remove_commands` left by a layer without
+    // include or commands, e.g. an overlay leaving out commands of the config
+    // file, to the merged command list.
+    pub fn remove_commands(&mut self) -> anyhow::Result<()> {
+        const REMOVE_COMMANDS_PATH: &str = "command_configuration.remove_commands";
+        const COMMANDS_PATH: &str = "command_configuration.commands";
+
+        let Some(toml::Value::Table(command_configuration)) =
+            self.table.get_mut("command_configuration")
+        else {
+            return Ok(());
+        };
+
+        let Some(remove_commands) = command_configuration.remove("remove_commands") else {
+            return Ok(());
+        };
+
+        let layer = self
+            .origins
+            .remove(REMOVE_COMMANDS_PATH)
+            .unwrap_or(ConfigLayer::Defaults);
+
+        let remove_commands: Vec<String> = remove_commands
+            .try_into()
+            .with_context(|| format!("{REMOVE_COMMANDS_PATH} from {layer} must be strings"))?;
+
+        let Some(toml::Value::Array(commands)) = command_configuration.get_mut("commands") else {
+            anyhow::bail!("{REMOVE_COMMANDS_PATH} from {layer} has no commands to remove");
+        };
+
+        for id in remove_commands {
+            let has_id = |command: &toml::Value| {
+                command.get("id").and_then(toml::Value::as_str) == Some(&id)
+            };
+
+            if !commands.iter().any(has_id) {
+                anyhow::bail!("{REMOVE_COMMANDS_PATH} id '{id}' from {layer} is not a command");
+            }
+
+            commands.retain(|command| !has_id(command));
+        }
+
+        self.origins.insert(COMMANDS_PATH.to_owned(), layer);
+
+        Ok(())
+    }
+
+    pub fn into_table(self) -> toml::Table {
+        self.table
+    }
+
+    // One `key = value # layer` line per leaf value. Values are taken from the
+    // configuration serialized like check-config prints it, so secrets are
+    // redacted.
+    pub fn origins_report(&self, configuration: &Configuration) -> anyhow::Result<String> {
+        let table =
+            toml::Table::try_from(configuration).context("error serializing configuration")?;
+
+        Ok(self
+            .origins
+            .iter()
+            .map(|(path, layer)| {
+                let value = lookup(&table, path)
+                    .map_or_else(|| "[missing]".to_owned(), toml::Value::to_string);
+                format!("{path} = {value} # {layer}\n")
+            })
+            .collect())
+    }
+}
+
+fn join_path(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{prefix}.{key}")
+    }
+}
+
+fn lookup<'a>(table: &'a toml::Table, path: &str) -> Option<&'a toml::Value> {
+    let mut keys = path.split('.');
+    let mut value = table.get(keys.next()?)?;
+    for key in keys {
+        value = value.as_table()?.get(key)?;
+    }
+    Some(value)
+}
+
+fn record_origins(
+    origins: &mut BTreeMap<String, ConfigLayer>,
+    path: &str,
+    layer: &ConfigLayer,
+    value: &toml::Value,
+) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                record_origins(origins, &join_path(path, key), layer, value);
+            }
+        }
+        _ => {
+            origins.insert(path.to_owned(), layer.clone());
+        }
+    }
+}
+
+fn merge_table(
+    target: &mut toml::Table,
+    origins: &mut BTreeMap<String, ConfigLayer>,
+    prefix: &str,
+    layer: &ConfigLayer,
+    source: toml::Table,
+) {
+    for (key, value) in source {
+        let path = join_path(prefix, &key);
+
+        match (target.get_mut(&key), value) {
+            (Some(toml::Value::Table(target_table)), toml::Value::Table(source_table)) => {
+                merge_table(target_table, origins, &path, layer, source_table);
+            }
+            (_, value) => {
+                // a replaced value or table no longer has its previous leaves
+                let nested_prefix = format!("{path}.");
+                origins.retain(|origin_path, _| {
+                    origin_path != &path && !origin_path.starts_with(&nested_prefix)
+                });
+
+                record_origins(origins, &path, layer, &value);
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMANDS_PATH: &str = "command_configuration.commands";
+
+    fn table(toml: &str) -> toml::Table {
+        toml.parse().unwrap()
+    }
+
+    fn configuration(layered_configuration: &LayeredConfiguration) -> Configuration {
+        toml::Value::Table(layered_configuration.table.clone())
+            .try_into()
+            .unwrap()
+    }
+
+    fn origin(layered_configuration: &LayeredConfiguration, path: &str) -> Option<String> {
+        layered_configuration
+            .origins
+            .get(path)
+            .map(ToString::to_string)
+    }
+
+    fn value<'a>(layered_configuration: &'a LayeredConfiguration, path: &str) -> &'a toml::Value {
+        lookup(&layered_configuration.table, path).unwrap()
+    }
+
+    #[test]
+    fn parse_values() {
+        let cases = [
+            ("10", toml::Value::Integer(10)),
+            ("true", toml::Value::Boolean(true)),
+            (
+                r#"["a", "b"]"#,
+                toml::Value::Array(vec!["a".into(), "b".into()]),
+            ),
+            (r#""quoted""#, toml::Value::String("quoted".to_owned())),
+            ("[::1]:8080", toml::Value::String("[::1]:8080".to_owned())),
+            ("5 seconds", toml::Value::String("5 seconds".to_owned())),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(parse_value(value), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn merge_tracks_origins() {
+        let mut layered_configuration = LayeredConfiguration::with_defaults().unwrap();
+
+        assert_eq!(
+            origin(&layered_configuration, "server_configuration.context").as_deref(),
+            Some("defaults")
+        );
+
+        layered_configuration.merge(
+            &ConfigLayer::File("config.toml".to_owned()),
+            table(
+                r#"
+                [server_configuration]
+                context = "/file"
+                network_policy = { internal_networks = ["10.0.0.0/8"] }
+                "#,
+            ),
+        );
+        layered_configuration.merge(
+            &ConfigLayer::Overlay("overlay.toml".to_owned()),
+            table(r#"server_configuration = { context = "/overlay" }"#),
+        );
+
+        let cases = [
+            (
+                "server_configuration.context",
+                "overlay overlay.toml",
+                "/overlay",
+            ),
+            ("server_configuration.bind_address", "defaults", "[::]:8080"),
+        ];
+
+        for (path, expected_origin, expected_value) in cases {
+            assert_eq!(
+                origin(&layered_configuration, path).as_deref(),
+                Some(expected_origin),
+                "{path}"
+            );
+            assert_eq!(
+                value(&layered_configuration, path).as_str(),
+                Some(expected_value)
+            );
+        }
+
+        // arrays are leaves, their elements have no origins of their own
+        assert_eq!(
+            origin(
+                &layered_configuration,
+                "server_configuration.network_policy.internal_networks"
+            )
+            .as_deref(),
+            Some("file config.toml")
+        );
+    }
+
+    #[test]
+    fn replaced_table_drops_nested_origins() {
+        let mut layered_configuration = LayeredConfiguration::default();
+
+        layered_configuration.merge(&ConfigLayer::Defaults, table("a = { b = 1, c = 2 }"));
+        layered_configuration.merge(&ConfigLayer::CommandLine("a=3".to_owned()), table("a = 3"));
+
+        assert_eq!(origin(&layered_configuration, "a.b"), None);
+        assert_eq!(origin(&layered_configuration, "a.c"), None);
+        assert_eq!(
+            origin(&layered_configuration, "a").as_deref(),
+            Some("--set a=3")
+        );
+    }
+
+    #[test]
+    fn environment_paths() {
+        let mut layered_configuration = LayeredConfiguration::default();
+
+        layered_configuration
+            .merge_environment([
+                (
+                    "RUST_AXUM__SERVER_CONFIGURATION__BIND_ADDRESS".into(),
+                    "[::1]:9000".into(),
+                ),
+                (
+                    "RUST_AXUM__COMMAND_CONFIGURATION__MAX_CONCURRENT_COMMANDS".into(),
+                    "4".into(),
+                ),
+                (
+                    "RUST_AXUM__SERVER_CONFIGURATION____CONTEXT".into(),
+                    "/x".into(),
+                ),
+                ("RUST_AXUM_".into(), "ignored".into()),
+                ("PATH".into(), "/usr/bin".into()),
+            ])
+            .unwrap();
+
+        let cases = [
+            (
+                "server_configuration.bind_address",
+                toml::Value::String("[::1]:9000".to_owned()),
+                "env RUST_AXUM__SERVER_CONFIGURATION__BIND_ADDRESS",
+            ),
+            (
+                "command_configuration.max_concurrent_commands",
+                toml::Value::Integer(4),
+                "env RUST_AXUM__COMMAND_CONFIGURATION__MAX_CONCURRENT_COMMANDS",
+            ),
+        ];
+
+        for (path, expected_value, expected_origin) in cases {
+            assert_eq!(
+                value(&layered_configuration, path),
+                &expected_value,
+                "{path}"
+            );
+            assert_eq!(
+                origin(&layered_configuration, path).as_deref(),
+                Some(expected_origin)
+            );
+        }
+
+        // the variable with an empty key and the unrelated ones are skipped
+        assert_eq!(layered_configuration.origins.len(), 2);
+    }
+
+    #[test]
+    fn environment_non_utf8() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let non_utf8 = |prefix: &str| {
+            let mut bytes = prefix.as_bytes().to_vec();
+            bytes.push(0xff);
+            OsString::from_vec(bytes)
+        };
+
+        let cases = [
+            // unrelated variables need not be UTF-8
+            ((non_utf8("OTHER_"), "value".into()), true),
+            (("OTHER".into(), non_utf8("value")), true),
+            ((non_utf8("RUST_AXUM__"), "value".into()), false),
+            (
+                (
+                    "RUST_AXUM__SERVER_CONFIGURATION__CONTEXT".into(),
+                    non_utf8("/"),
+                ),
+                false,
+            ),
+        ];
+
+        for (variable, expected_ok) in cases {
+            let mut layered_configuration = LayeredConfiguration::default();
+
+            let result = layered_configuration.merge_environment([variable.clone()]);
+
+            assert_eq!(result.is_ok(), expected_ok, "{variable:?}");
+            assert!(layered_configuration.origins.is_empty());
+        }
+    }
+
+    #[test]
+    fn command_line_overrides() {
+        let cases = [
+            ("server_configuration.context=/set", true),
+            (" server_configuration.context = /set ", true),
+            ("server_configuration.context", false),
+            ("server_configuration..context=/set", false),
+            ("=/set", false),
+        ];
+
+        for (set, expected_ok) in cases {
+            let mut layered_configuration = LayeredConfiguration::default();
+
+            let result = layered_configuration.merge_command_line(set);
+
+            assert_eq!(result.is_ok(), expected_ok, "{set}");
+            if expected_ok {
+                assert_eq!(
+                    value(&layered_configuration, "server_configuration.context").as_str(),
+                    Some("/set")
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn remove_commands() {
+        let commands = table(
+            r#"
+            [command_configuration]
+            commands = [
+                { id = "a", description = "a", command = "/bin/true" },
+                { id = "b", description = "b", command = "/bin/true" },
+            ]
+            "#,
+        );
+
+        let cases = [
+            (r#"["a"]"#, Ok(vec!["b"])),
+            (r#"["a", "b"]"#, Ok(vec![])),
+            (r#"[]"#, Ok(vec!["a", "b"])),
+            (
+                r#"["c"]"#,
+                Err("id 'c' from overlay overlay.toml is not a command"),
+            ),
+            (r#""a""#, Err("from overlay overlay.toml must be strings")),
+        ];
+
+        for (remove_commands, expected) in cases {
+            let mut layered_configuration = LayeredConfiguration::default();
+            layered_configuration.merge(
+                &ConfigLayer::File("config.toml".to_owned()),
+                commands.clone(),
+            );
+            layered_configuration.merge(
+                &ConfigLayer::Overlay("overlay.toml".to_owned()),
+                table(&format!(
+                    "command_configuration = {{ remove_commands = {remove_commands} }}"
+                )),
+            );
+
+            let result = layered_configuration.remove_commands();
+
+            match expected {
+                Ok(expected_ids) => {
+                    result.unwrap();
+                    let ids: Vec<&str> = value(&layered_configuration, COMMANDS_PATH)
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|command| command["id"].as_str().unwrap())
+                        .collect();
+                    assert_eq!(ids, expected_ids, "{remove_commands}");
+                    assert_eq!(
+                        origin(&layered_configuration, COMMANDS_PATH).as_deref(),
+                        Some("overlay overlay.toml")
+                    );
+                    assert!(
+                        lookup(
+                            &layered_configuration.table,
+                            "command_configuration.remove_commands"
+                        )
+                        .is_none()
+                    );
+                }
+                Err(expected_message) => {
+                    let error = format!("{:#}", result.unwrap_err());
+                    assert!(error.contains(expected_message), "{error}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn remove_commands_without_commands() {
+        let mut layered_configuration = LayeredConfiguration::default();
+        layered_configuration.merge(
+            &ConfigLayer::Overlay("overlay.toml".to_owned()),
+            table(r#"command_configuration = { remove_commands = ["a"] }"#),
+        );
+
+        let error = format!("{:#}", layered_configuration.remove_commands().unwrap_err());
+
+        assert!(error.contains("has no commands to remove"), "{error}");
+    }
+
+    #[test]
+    fn origins_report_redacts_secrets() {
+        const TOKEN_SHA256: &str =
+            "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b";
+        const PASSWORD_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHQ$aGFzaGhhc2hoYXNo";
+
+        let mut layered_configuration = LayeredConfiguration::with_defaults().unwrap();
+        layered_configuration.merge(
+            &ConfigLayer::File("config.toml".to_owned()),
+            table(&format!(
+                r#"
+                [auth_configuration]
+                api_tokens = [{{ name = "ops", token_sha256 = "{TOKEN_SHA256}" }}]
+                users = [{{ username = "admin", password_hash = "{PASSWORD_HASH}" }}]
+                "#
+            )),
+        );
+
+        let report = layered_configuration
+            .origins_report(&configuration(&layered_configuration))
+            .unwrap();
+
+        assert!(report.contains("auth_configuration.api_tokens = "));
+        assert!(report.contains("auth_configuration.users = "));
+        assert!(report.contains("[redacted]"));
+        assert!(!report.contains(TOKEN_SHA256));
+        assert!(!report.contains(PASSWORD_HASH));
+    }
+}
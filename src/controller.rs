@@ -1,53 +1,158 @@
+#[cfg(feature = "commands")]
+mod audit_log;
+mod auth;
+#[cfg(feature = "commands")]
 mod commands;
+mod configuration;
+#[cfg(feature = "connection-info")]
 mod connection_info;
 mod health;
+mod plugin_status;
 mod request_info;
 mod version_info;
 
-use axum::{Router, routing::get};
+use axum::{Router, extract::FromRequestParts, http::request::Parts, middleware, routing::get};
 
-use std::sync::Arc;
+use std::{convert::Infallible, sync::Arc};
+
+#[cfg(feature = "commands")]
+use crate::service::{audit_service::AuditService, command_service::CommandsService};
+
+#[cfg(feature = "connection-info")]
+use crate::service::connection_service::ConnectionTrackerService;
 
 use crate::{
     config,
-    service::{command_service::CommandsService, connection_service::ConnectionTrackerService},
+    service::{
+        auth_service::AuthService, config_service::ConfigService, plugin_service::PluginService,
+    },
+    utils::forwarded::ClientInfo,
 };
 
-fn create_api_routes(
+#[cfg(feature = "commands")]
+fn create_command_routes(
     commands_service: Arc<impl CommandsService>,
-    connection_tracker_service: Arc<impl ConnectionTrackerService>,
+    audit_service: Arc<impl AuditService>,
 ) -> Router {
+    let run_command_routes = Router::new()
+        .route("/{id}", get(commands::run_command))
+        .with_state((Arc::clone(&commands_service), Arc::clone(&audit_service)));
+
     let command_routes = Router::new()
         .route("/", get(commands::all_commands))
-        .route("/{id}", get(commands::run_command))
+        .route("/{id}/history", get(commands::command_history))
+        .with_state(Arc::clone(&commands_service))
+        .merge(run_command_routes);
+
+    let command_status_routes = Router::new()
+        .route("/", get(commands::commands_status))
         .with_state(commands_service);
 
-    let connection_routes = Router::new()
-        .route("/", get(connection_info::connection_info))
-        .with_state(connection_tracker_service);
+    let audit_log_routes = Router::new()
+        .route("/", get(audit_log::audit_log))
+        .with_state(audit_service);
 
     Router::new()
         .nest("/commands", command_routes)
-        .nest("/connection_info", connection_routes)
+        .nest("/command_status", command_status_routes)
+        .nest("/audit_log", audit_log_routes)
+}
+
+fn create_api_routes(
+    #[cfg(feature = "commands")] commands_service: Arc<impl CommandsService>,
+    #[cfg(feature = "connection-info")] connection_tracker_service: Arc<
+        impl ConnectionTrackerService,
+    >,
+    #[cfg(feature = "commands")] audit_service: Arc<impl AuditService>,
+    auth_service: Arc<impl AuthService>,
+    config_service: Arc<impl ConfigService>,
+    plugin_service: Arc<impl PluginService>,
+) -> Router {
+    let auth_status_routes = Router::new()
+        .route("/", get(auth::auth_status))
+        .with_state(Arc::clone(&auth_service));
+
+    let configuration_routes = Router::new()
+        .route("/", get(configuration::configuration))
+        .with_state(config_service);
+
+    let plugin_status_routes = Router::new()
+        .route("/", get(plugin_status::plugin_status))
+        .with_state(Arc::clone(&plugin_service));
+
+    let version_info_routes = Router::new()
+        .route("/", get(version_info::version_info))
+        .with_state(Arc::clone(&plugin_service));
+
+    let routes = Router::new()
+        .nest("/auth_status", auth_status_routes)
+        .nest("/configuration", configuration_routes)
+        .nest("/plugin_status", plugin_status_routes)
         .route("/request_info", get(request_info::request_info))
-        .route("/version_info", get(version_info::version_info))
+        .nest("/version_info", version_info_routes);
+
+    #[cfg(feature = "commands")]
+    let routes = routes.merge(create_command_routes(commands_service, audit_service));
+
+    #[cfg(feature = "connection-info")]
+    let routes = routes.nest(
+        "/connection_info",
+        Router::new()
+            .route("/", get(connection_info::connection_info))
+            .with_state(connection_tracker_service),
+    );
+
+    routes
+        // builder and plugin routes are authenticated like the built-in ones
+        .merge(plugin_service.routes())
+        .layer(middleware::from_fn_with_state(
+            auth_service,
+            auth::authenticate,
+        ))
 }
 
 pub fn create_routes(
     server_configuration: &config::ServerConfiguration,
-    commands_service: Arc<impl CommandsService>,
-    connection_tracker_service: Arc<impl ConnectionTrackerService>,
+    #[cfg(feature = "commands")] commands_service: Arc<impl CommandsService>,
+    #[cfg(feature = "connection-info")] connection_tracker_service: Arc<
+        impl ConnectionTrackerService,
+    >,
+    #[cfg(feature = "commands")] audit_service: Arc<impl AuditService>,
+    auth_service: Arc<impl AuthService>,
+    config_service: Arc<impl ConfigService>,
+    plugin_service: Arc<impl PluginService>,
 ) -> Router {
-    Router::new().route("/health", get(health::health)).nest(
-        &server_configuration.context,
-        create_api_routes(commands_service, connection_tracker_service),
-    )
+    Router::new()
+        .route("/health", get(health::health))
+        .with_state(Arc::clone(&plugin_service))
+        .nest(
+            &server_configuration.context,
+            create_api_routes(
+                #[cfg(feature = "commands")]
+                commands_service,
+                #[cfg(feature = "connection-info")]
+                connection_tracker_service,
+                #[cfg(feature = "commands")]
+                audit_service,
+                auth_service,
+                config_service,
+                plugin_service,
+            ),
+        )
 }
 
-fn host_is_external(host: &str) -> bool {
-    config::instance()
-        .server_configuration
-        .external_hosts
-        .iter()
-        .any(|external_host| host == external_host)
+// Whether the client is outside the configured internal networks. Requests
+// without client info are treated as external.
+pub struct ExternalRequest(pub bool);
+
+impl<S: Send + Sync> FromRequestParts<S> for ExternalRequest {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(client_info) = parts.extensions.get::<ClientInfo>() else {
+            return Ok(Self(true));
+        };
+
+        Ok(Self(!client_info.internal))
+    }
 }
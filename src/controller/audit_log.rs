@@ -0,0 +1,43 @@
+use axum::{
+    Extension, Json,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+use std::sync::Arc;
+
+use crate::service::{
+    audit_service::{AuditLogError, AuditQuery, AuditService},
+    auth_service::Principal,
+};
+
+use super::{ExternalRequest, auth::AccessDenied};
+
+impl IntoResponse for AuditLogError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::NotFound => StatusCode::NOT_FOUND.into_response(),
+            Self::ReadError => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
+
+pub async fn audit_log(
+    ExternalRequest(external_request): ExternalRequest,
+    Extension(principal): Extension<Principal>,
+    Query(audit_query): Query<AuditQuery>,
+    State(audit_service): State<Arc<impl AuditService>>,
+) -> Response {
+    if !principal.may_view_status(external_request) {
+        return (StatusCode::NOT_FOUND, Extension(AccessDenied)).into_response();
+    }
+
+    match audit_service
+        .recent_records(external_request, audit_query)
+        .await
+    {
+        Ok(audit_records) => Json(audit_records).into_response(),
+        Err(error) => error.into_response(),
+    }
+}
@@ -0,0 +1,54 @@
+use axum::{
+    Extension, Json,
+    extract::{OriginalUri, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use std::sync::Arc;
+
+use crate::service::auth_service::{AuthService, Principal};
+
+use super::ExternalRequest;
+
+// Response extension set by handlers that deny access to the principal.
+#[derive(Clone, Copy, Debug)]
+pub struct AccessDenied;
+
+// Inserts the request `Principal` as an extension and records denied
+// responses.
+pub async fn authenticate(
+    State(auth_service): State<Arc<impl AuthService>>,
+    OriginalUri(original_uri): OriginalUri,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let principal = auth_service.authenticate(request.headers()).await;
+
+    request.extensions_mut().insert(principal.clone());
+
+    let response = next.run(request).await;
+
+    if response.extensions().get::<AccessDenied>().is_some() {
+        auth_service.record_access_denied(&principal, &original_uri);
+    }
+
+    response
+}
+
+pub async fn auth_status(
+    ExternalRequest(external_request): ExternalRequest,
+    Extension(principal): Extension<Principal>,
+    State(auth_service): State<Arc<impl AuthService>>,
+) -> Response {
+    if external_request {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    if !principal.may_view_status(external_request) {
+        return (StatusCode::NOT_FOUND, Extension(AccessDenied)).into_response();
+    }
+
+    Json(auth_service.auth_status()).into_response()
+}
@@ -1,49 +1,223 @@
 use axum::{
-    Json,
-    extract::{Path, State},
-    http::StatusCode,
+    Extension, Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
     response::{IntoResponse, Response},
 };
 
-use axum_extra::extract::Host;
+use serde::Deserialize;
 
 use std::sync::Arc;
 
-use crate::service::command_service::{CommandID, CommandsService, RunCommandDTO, RunCommandError};
+use crate::{
+    service::{
+        audit_service::{AuditRecordDTO, AuditRequestInfo, AuditService},
+        auth_service::Principal,
+        command_service::{
+            CommandHistoryDTO, CommandID, CommandsService, RunCommandDTO, RunCommandError,
+        },
+    },
+    utils::forwarded::ClientInfo,
+};
 
 use tracing::debug;
 
-use super::host_is_external;
+use super::{ExternalRequest, auth::AccessDenied};
+
+mod format;
+
+use format::OutputFormat;
 
 impl IntoResponse for RunCommandError {
     fn into_response(self) -> Response {
         match self {
             Self::CommandNotFound => StatusCode::NOT_FOUND.into_response(),
-            Self::SemaphoreAcquireError => StatusCode::TOO_MANY_REQUESTS.into_response(),
+            Self::SemaphoreAcquireError {
+                queue_position,
+                retry_after,
+            } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after.as_secs())],
+                Json(serde_json::json!({ "queue_position": queue_position })),
+            )
+                .into_response(),
+            Self::SandboxSetupError => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            // same as not found so hidden commands are not disclosed
+            Self::AccessDenied => (StatusCode::NOT_FOUND, Extension(AccessDenied)).into_response(),
         }
     }
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffMode {
+    Previous,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RunCommandQuery {
+    diff: Option<DiffMode>,
+    format: Option<OutputFormat>,
+}
+
 pub async fn all_commands(
-    Host(host): Host,
+    ExternalRequest(external_request): ExternalRequest,
+    Extension(principal): Extension<Principal>,
     State(commands_service): State<Arc<impl CommandsService>>,
 ) -> impl IntoResponse {
-    let external_request = host_is_external(&host);
-    Json(commands_service.all_commands(external_request))
+    Json(commands_service.all_commands(external_request, &principal))
+}
+
+pub async fn commands_status(
+    ExternalRequest(external_request): ExternalRequest,
+    Extension(principal): Extension<Principal>,
+    State(commands_service): State<Arc<impl CommandsService>>,
+) -> Response {
+    if !principal.may_view_status(external_request) {
+        return (StatusCode::NOT_FOUND, Extension(AccessDenied)).into_response();
+    }
+
+    Json(commands_service.commands_status(external_request, &principal)).into_response()
 }
 
 pub async fn run_command(
-    Host(host): Host,
+    ExternalRequest(external_request): ExternalRequest,
     Path(id): Path<String>,
-    State(commands_service): State<Arc<impl CommandsService>>,
-) -> Result<Json<RunCommandDTO>, RunCommandError> {
-    debug!(host, id, "run_command");
+    Query(run_command_query): Query<RunCommandQuery>,
+    Extension(client_info): Extension<ClientInfo>,
+    Extension(principal): Extension<Principal>,
+    request_headers: HeaderMap,
+    State((commands_service, audit_service)): State<(
+        Arc<impl CommandsService>,
+        Arc<impl AuditService>,
+    )>,
+) -> Result<Response, RunCommandError> {
+    debug!(external_request, id, "run_command");
+
+    let command_id = CommandID(id);
+
+    let run_command_result = commands_service
+        .run_command(external_request, &principal, command_id.clone())
+        .await;
+
+    let audit_request_info = AuditRequestInfo {
+        // set by the server, inbound x-request-id headers are removed
+        request_id: request_headers
+            .get("x-request-id")
+            .and_then(|request_id| request_id.to_str().ok())
+            .unwrap_or("[Unknown]")
+            .to_owned(),
+        principal: principal.name().to_owned(),
+        client_info,
+        external_request,
+    };
+
+    audit_service
+        .record_command(AuditRecordDTO::new(
+            audit_request_info,
+            &command_id,
+            &run_command_result,
+        ))
+        .await;
+
+    let mut response = run_command_result?;
+
+    if let Some(DiffMode::Previous) = run_command_query.diff {
+        response = commands_service.diff_previous_run(response);
+    }
+
+    let output_format = run_command_query
+        .format
+        .unwrap_or_else(|| OutputFormat::negotiate(&request_headers));
+
+    Ok(run_command_response(
+        &request_headers,
+        output_format,
+        response,
+    ))
+}
 
-    let external_request = host_is_external(&host);
+pub async fn command_history(
+    ExternalRequest(external_request): ExternalRequest,
+    Path(id): Path<String>,
+    Extension(principal): Extension<Principal>,
+    State(commands_service): State<Arc<impl CommandsService>>,
+) -> Result<Json<CommandHistoryDTO>, RunCommandError> {
+    debug!(external_request, id, "command_history");
 
-    let response = commands_service
-        .run_command(external_request, CommandID(id))
-        .await?;
+    let response = commands_service.command_history(external_request, &principal, CommandID(id))?;
 
     Ok(Json(response))
 }
+
+fn run_command_response(
+    request_headers: &HeaderMap,
+    output_format: OutputFormat,
+    response: RunCommandDTO,
+) -> Response {
+    let etag = output_format.etag(&response.etag());
+
+    let mut response_headers = HeaderMap::new();
+
+    response_headers.insert(header::VARY, HeaderValue::from_static("accept"));
+
+    if let Ok(etag_value) = HeaderValue::from_str(&etag) {
+        response_headers.insert(header::ETAG, etag_value);
+    }
+
+    if let Some(cache_age) = response.cache_age() {
+        response_headers.insert(header::AGE, cache_age.as_secs().into());
+    }
+
+    if if_none_match(request_headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, response_headers).into_response();
+    }
+
+    output_format.render(response_headers, response)
+}
+
+fn if_none_match(request_headers: &HeaderMap, etag: &str) -> bool {
+    request_headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|value| value.trim())
+        .any(|value| value == "*" || value.trim_start_matches("W/") == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn if_none_match_etags() {
+        let etag = "\"0123456789abcdef\"";
+
+        let cases = [
+            (vec![], false),
+            (vec!["\"0123456789abcdef\""], true),
+            (vec!["W/\"0123456789abcdef\""], true),
+            (vec!["\"fedcba9876543210\""], false),
+            (vec!["\"fedcba9876543210\", W/\"0123456789abcdef\""], true),
+            (vec!["\"fedcba9876543210\"", "\"0123456789abcdef\""], true),
+            (vec!["*"], true),
+            // quotes are part of the tag
+            (vec!["0123456789abcdef"], false),
+            (vec!["\"text-0123456789abcdef\""], false),
+        ];
+
+        for (values, expected) in cases {
+            let mut request_headers = HeaderMap::new();
+            for value in &values {
+                request_headers.append(header::IF_NONE_MATCH, HeaderValue::from_static(value));
+            }
+
+            assert_eq!(
+                if_none_match(&request_headers, etag),
+                expected,
+                "{values:?}"
+            );
+        }
+    }
+}
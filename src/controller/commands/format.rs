@@ -0,0 +1,219 @@
+use axum::{
+    Json,
+    http::{HeaderMap, HeaderName, HeaderValue, header},
+    response::{Html, IntoResponse, Response},
+};
+
+use serde::Deserialize;
+
+use std::fmt::Write;
+
+use crate::service::command_service::RunCommandDTO;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    Json,
+    Text,
+    Html,
+}
+
+impl OutputFormat {
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "application/json" | "application/*" | "*/*" => Some(Self::Json),
+            "text/plain" | "text/*" => Some(Self::Text),
+            "text/html" => Some(Self::Html),
+            _ => None,
+        }
+    }
+
+    // The supported media type with the highest quality in the Accept header,
+    // json if there is none. Specific types win over wildcards of equal quality.
+    pub fn negotiate(request_headers: &HeaderMap) -> Self {
+        request_headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|media_range| {
+                let mut params = media_range.split(';').map(str::trim);
+
+                let output_format = Self::from_media_type(&params.next()?.to_ascii_lowercase())?;
+
+                let quality = params
+                    .find_map(|param| param.strip_prefix("q="))
+                    .map_or(Some(1.0), |quality| quality.parse::<f32>().ok())?;
+
+                let specific = !media_range.contains('*');
+
+                (quality > 0.0).then_some((quality, specific, output_format))
+            })
+            .reduce(|best, candidate| {
+                if (candidate.0, candidate.1) > (best.0, best.1) {
+                    candidate
+                } else {
+                    best
+                }
+            })
+            .map_or(Self::Json, |(_, _, output_format)| output_format)
+    }
+
+    // Each representation of a run needs a distinct entity tag.
+    pub fn etag(self, etag: &str) -> String {
+        match self {
+            Self::Json => etag.to_owned(),
+            Self::Text => etag.replacen('"', "\"text-", 1),
+            Self::Html => etag.replacen('"', "\"html-", 1),
+        }
+    }
+
+    pub fn render(self, mut response_headers: HeaderMap, response: RunCommandDTO) -> Response {
+        match self {
+            Self::Json => (response_headers, Json(response)).into_response(),
+            Self::Text => {
+                insert_metadata_headers(&mut response_headers, &response);
+                let body = response
+                    .unified_diff()
+                    .unwrap_or(response.command_output())
+                    .to_owned();
+                (response_headers, body).into_response()
+            }
+            Self::Html => (response_headers, Html(html_page(&response))).into_response(),
+        }
+    }
+}
+
+fn insert_metadata_headers(response_headers: &mut HeaderMap, response: &RunCommandDTO) {
+    let mut insert = |name: &'static str, value: String| {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response_headers.insert(HeaderName::from_static(name), value);
+        }
+    };
+
+    insert("x-command-id", response.command_id().to_owned());
+    insert("x-command-timestamp", response.now().to_owned());
+    insert(
+        "x-command-duration-ms",
+        response.command_duration_ms().to_string(),
+    );
+    if let Some(exit_status) = response.exit_status() {
+        insert("x-command-exit-status", exit_status.to_string());
+    }
+    insert("x-command-cached", response.cached().to_string());
+}
+
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn html_page(response: &RunCommandDTO) -> String {
+    let command_id = html_escape(response.command_id());
+
+    let mut summary = format!(
+        "{} duration {} ms",
+        html_escape(response.now()),
+        response.command_duration_ms()
+    );
+    if let Some(exit_status) = response.exit_status() {
+        let _ = write!(summary, " exit status {exit_status}");
+    }
+    if response.cached() {
+        summary.push_str(" cached");
+    }
+
+    let output = html_escape(response.unified_diff().unwrap_or(response.command_output()));
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{command_id}</title>
+</head>
+<body>
+<h1>{command_id}</h1>
+<p>{summary}</p>
+<pre>{output}</pre>
+</body>
+</html>
+"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(values: &[&'static str]) -> HeaderMap {
+        let mut request_headers = HeaderMap::new();
+        for value in values {
+            request_headers.append(header::ACCEPT, HeaderValue::from_static(value));
+        }
+        request_headers
+    }
+
+    #[test]
+    fn negotiate() {
+        let cases = [
+            (vec![], OutputFormat::Json),
+            (vec!["text/plain"], OutputFormat::Text),
+            (vec!["TEXT/HTML"], OutputFormat::Html),
+            (vec!["image/png"], OutputFormat::Json),
+            // browsers
+            (
+                vec!["text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"],
+                OutputFormat::Html,
+            ),
+            // specific types win over wildcards of equal quality
+            (vec!["*/*, text/plain"], OutputFormat::Text),
+            (vec!["text/*, text/html"], OutputFormat::Html),
+            // quality wins over specific types
+            (vec!["text/plain;q=0.5, */*"], OutputFormat::Json),
+            (vec!["text/html;q=0.2, text/*;q=0.4"], OutputFormat::Text),
+            (vec!["text/plain; q=0.9", "text/html"], OutputFormat::Html),
+            // q=0 excludes a type, invalid qualities are ignored
+            (vec!["text/plain;q=0, text/*;q=0.1"], OutputFormat::Text),
+            (
+                vec!["text/html;q=abc, text/plain;q=0.1"],
+                OutputFormat::Text,
+            ),
+        ];
+
+        for (values, expected) in cases {
+            assert_eq!(
+                OutputFormat::negotiate(&accept(&values)),
+                expected,
+                "{values:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn distinct_etags() {
+        let etag = "\"0123456789abcdef\"";
+
+        assert_eq!(OutputFormat::Json.etag(etag), etag);
+        assert_eq!(OutputFormat::Text.etag(etag), "\"text-0123456789abcdef\"");
+        assert_eq!(OutputFormat::Html.etag(etag), "\"html-0123456789abcdef\"");
+    }
+
+    #[test]
+    fn html_escapes() {
+        assert_eq!(
+            html_escape(r#"<a href="x">'&'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+    }
+}
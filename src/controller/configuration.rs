@@ -0,0 +1,25 @@
+use axum::{
+    Extension, Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+use std::sync::Arc;
+
+use crate::service::{auth_service::Principal, config_service::ConfigService};
+
+use super::{ExternalRequest, auth::AccessDenied};
+
+pub async fn configuration(
+    ExternalRequest(external_request): ExternalRequest,
+    Extension(principal): Extension<Principal>,
+    State(config_service): State<Arc<impl ConfigService>>,
+) -> Response {
+    // same as not found so the endpoint is not disclosed
+    if !principal.may_view_status(external_request) {
+        return (StatusCode::NOT_FOUND, Extension(AccessDenied)).into_response();
+    }
+
+    Json(config_service.effective_configuration()).into_response()
+}
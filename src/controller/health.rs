@@ -1,5 +1,20 @@
-use axum::{http::StatusCode, response::IntoResponse};
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
 
-pub async fn health() -> impl IntoResponse {
-    (StatusCode::OK, "all good")
+use std::sync::Arc;
+
+use crate::service::plugin_service::PluginService;
+
+pub async fn health(State(plugin_service): State<Arc<impl PluginService>>) -> Response {
+    let health = plugin_service.health();
+
+    if !health.healthy() {
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(health)).into_response();
+    }
+
+    (StatusCode::OK, "all good").into_response()
 }
@@ -0,0 +1,28 @@
+use axum::{
+    Extension, Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+use std::sync::Arc;
+
+use crate::service::{auth_service::Principal, plugin_service::PluginService};
+
+use super::{ExternalRequest, auth::AccessDenied};
+
+pub async fn plugin_status(
+    ExternalRequest(external_request): ExternalRequest,
+    Extension(principal): Extension<Principal>,
+    State(plugin_service): State<Arc<impl PluginService>>,
+) -> Response {
+    if external_request {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    if !principal.may_view_status(external_request) {
+        return (StatusCode::NOT_FOUND, Extension(AccessDenied)).into_response();
+    }
+
+    Json(plugin_service.plugin_status()).into_response()
+}
@@ -1,20 +1,16 @@
 use axum::{
-    Json,
-    body::Body,
-    extract::{ConnectInfo, OriginalUri},
-    http::Request,
-    response::IntoResponse,
+    Extension, Json, body::Body, extract::OriginalUri, http::Request, response::IntoResponse,
 };
 
-use crate::service::{connection_service::ConnectionID, request_info_service};
+use crate::{service::request_info_service, utils::forwarded::ClientInfo};
 
 pub async fn request_info(
-    ConnectInfo(connection_id): ConnectInfo<ConnectionID>,
+    Extension(client_info): Extension<ClientInfo>,
     OriginalUri(original_uri): OriginalUri,
     request: Request<Body>,
 ) -> impl IntoResponse {
     Json(request_info_service::request_info(
-        connection_id,
+        client_info,
         original_uri,
         request,
     ))
@@ -1,7 +1,13 @@
-use axum::{Json, response::IntoResponse};
+use axum::{Json, extract::State, response::IntoResponse};
 
-use crate::service::version_service;
+use std::sync::Arc;
 
-pub async fn version_info() -> impl IntoResponse {
-    Json(version_service::verison_info())
+use crate::service::{plugin_service::PluginService, version_service};
+
+pub async fn version_info(
+    State(plugin_service): State<Arc<impl PluginService>>,
+) -> impl IntoResponse {
+    Json(version_service::version_info_dto(
+        plugin_service.plugin_names(),
+    ))
 }
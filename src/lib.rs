@@ -0,0 +1,10 @@
+pub mod application;
+pub mod config;
+pub mod controller;
+pub mod plugin;
+pub mod service;
+pub mod utils;
+
+pub use application::{
+    BuilderCommandsService, PluginServerBuilder, ServerBuilder, ServerHandle, new_server_builder,
+};
@@ -1,42 +1,142 @@
-mod application;
-mod config;
-mod controller;
-mod service;
-mod utils;
-
-use anyhow::Context;
+use clap::{Args, Parser, Subcommand};
 
 use tracing::{error, info};
 
-fn log_version_info() {
-    for (key, value) in crate::service::version_service::verison_info() {
-        info!(key, value, "version info");
+use std::process::ExitCode;
+
+use rust_axum::{application, config, service};
+
+// Exit codes: 0 success, 1 error, 2 usage error (reported by clap),
+// application::COMMAND_FAILED_EXIT_CODE when a command did not succeed.
+#[derive(Debug, Parser)]
+#[command(version, about = "Run configured commands over http")]
+struct Cli {
+    #[command(subcommand)]
+    command: CliCommand,
+}
+
+#[derive(Debug, Args)]
+struct ConfigArgs {
+    config_file: String,
+    #[arg(
+        long = "overlay",
+        value_name = "FILE",
+        help = "Merge a toml file over the config file"
+    )]
+    overlay_files: Vec<String>,
+    #[arg(
+        long = "set",
+        value_name = "KEY=VALUE",
+        help = "Override a value, e.g. server_configuration.bind_address=[::1]:8080"
+    )]
+    overrides: Vec<String>,
+}
+
+impl From<ConfigArgs> for config::ConfigSources {
+    fn from(config_args: ConfigArgs) -> Self {
+        Self {
+            config_file: config_args.config_file,
+            overlay_files: config_args.overlay_files,
+            overrides: config_args.overrides,
+        }
     }
 }
 
-fn process_name() -> String {
-    std::env::args().next().unwrap_or("[UNKNOWN]".to_owned())
+#[derive(Debug, Subcommand)]
+enum CliCommand {
+    #[command(about = "Run the http server")]
+    Serve {
+        #[command(flatten)]
+        config_args: ConfigArgs,
+    },
+    #[command(about = "Validate the configuration and print it with defaults filled in")]
+    CheckConfig {
+        #[command(flatten)]
+        config_args: ConfigArgs,
+        #[arg(long, help = "Print each value with the layer it came from")]
+        show_origin: bool,
+    },
+    #[command(about = "Print the JSON Schema of the config file")]
+    ConfigSchema,
+    #[cfg(feature = "commands")]
+    #[command(about = "List configured commands")]
+    ListCommands {
+        #[command(flatten)]
+        config_args: ConfigArgs,
+    },
+    #[cfg(feature = "commands")]
+    #[command(about = "Run a configured command once as the server would")]
+    RunCommand {
+        #[command(flatten)]
+        config_args: ConfigArgs,
+        command_id: String,
+    },
+    #[cfg(feature = "commands")]
+    #[command(about = "Run every configured command once and print a report")]
+    SelfTest {
+        #[command(flatten)]
+        config_args: ConfigArgs,
+    },
+    #[command(about = "Print version information")]
+    Version,
 }
 
-async fn try_main() -> anyhow::Result<()> {
-    log_version_info();
+fn log_version_info() {
+    for (key, value) in service::version_service::verison_info() {
+        info!(key, value, "version info");
+    }
+}
 
-    let config_file = std::env::args().nth(1).with_context(|| {
-        format!(
-            "config file required as command line argument: {} <config file>",
-            process_name(),
-        )
-    })?;
+async fn try_main(cli: Cli) -> anyhow::Result<ExitCode> {
+    match cli.command {
+        CliCommand::Serve { config_args } => {
+            log_version_info();
+            application::run(config_args.into()).await?;
+        }
+        CliCommand::CheckConfig {
+            config_args,
+            show_origin,
+        } => application::check_config(config_args.into(), show_origin).await?,
+        CliCommand::ConfigSchema => application::config_schema()?,
+        #[cfg(feature = "commands")]
+        CliCommand::ListCommands { config_args } => {
+            application::list_commands(config_args.into()).await?
+        }
+        #[cfg(feature = "commands")]
+        CliCommand::RunCommand {
+            config_args,
+            command_id,
+        } => return application::run_command(config_args.into(), command_id).await,
+        #[cfg(feature = "commands")]
+        CliCommand::SelfTest { config_args } => {
+            return application::self_test(config_args.into()).await;
+        }
+        CliCommand::Version => application::version()?,
+    }
 
-    application::run(config_file).await
+    Ok(ExitCode::SUCCESS)
 }
 
 #[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt::init();
+async fn async_main(cli: Cli) -> ExitCode {
+    // stdout is left for subcommand output
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
 
-    if let Err(error) = try_main().await {
-        error!(?error, "fatal error in main");
-        std::process::exit(1);
+    match try_main(cli).await {
+        Ok(exit_code) => exit_code,
+        Err(error) => {
+            error!(?error, "fatal error in main");
+            ExitCode::FAILURE
+        }
     }
 }
+
+fn main() -> ExitCode {
+    // before the tokio runtime starts any threads
+    #[cfg(feature = "commands")]
+    service::command_service::exec_command_launcher_if_requested();
+
+    async_main(Cli::parse())
+}
@@ -0,0 +1,47 @@
+use axum::Router;
+
+use tokio::task::JoinSet;
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use crate::config::SharedConfiguration;
+
+// What a plugin can use, with the service types of the builder it was added
+// to.
+pub struct PluginContext<C, T> {
+    pub shared_configuration: SharedConfiguration,
+    #[cfg(feature = "commands")]
+    pub commands_service: Arc<C>,
+    #[cfg(not(feature = "commands"))]
+    pub(crate) _commands_service: std::marker::PhantomData<C>,
+    pub connection_tracker_service: Arc<T>,
+}
+
+// Extends a server from outside this crate, added with
+// `ServerBuilder::plugin`. C and T are the commands and connection tracker
+// service types of the builder, so plugins usually implement `Plugin<C, T>`
+// for any C: CommandsService and T: ConnectionTrackerService.
+pub trait Plugin<C, T>: Send + Sync + 'static {
+    // listed in version_info and plugin_status
+    fn name(&self) -> &'static str;
+
+    // Routes served under server_configuration.context, authenticated like
+    // the built-in ones.
+    fn routes(&self, _context: &PluginContext<C, T>) -> Router {
+        Router::new()
+    }
+
+    // Background tasks are aborted when the server stops.
+    fn start_tasks(&self, _context: &PluginContext<C, T>, _tasks: &mut JoinSet<()>) {}
+
+    // Counters shown in plugin_status.
+    fn metrics(&self) -> BTreeMap<&'static str, usize> {
+        BTreeMap::new()
+    }
+
+    // Called on every /health request, so it should only read state kept up
+    // to date elsewhere, e.g. by a background task.
+    fn health_check(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
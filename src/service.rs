@@ -1,4 +1,10 @@
+#[cfg(feature = "commands")]
+pub mod audit_service;
+pub mod auth_service;
+#[cfg(feature = "commands")]
 pub mod command_service;
+pub mod config_service;
 pub mod connection_service;
+pub mod plugin_service;
 pub mod request_info_service;
 pub mod version_service;
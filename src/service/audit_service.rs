@@ -0,0 +1,362 @@
+use anyhow::Context;
+
+use serde::{Deserialize, Serialize};
+
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+
+use tracing::{debug, warn};
+
+use std::{
+    ffi::OsString,
+    io::ErrorKind,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    config,
+    service::command_service::{CommandID, RunCommandDTO, RunCommandError},
+    utils::{forwarded::ClientInfo, time::current_timestamp_string},
+};
+
+const DEFAULT_QUERY_LIMIT: usize = 100;
+
+const MAX_QUERY_LIMIT: usize = 1_000;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Completed,
+    CommandNotFound,
+    QueueFull,
+    SandboxSetupError,
+    AccessDenied,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuditRecordDTO {
+    timestamp: String,
+    request_id: String,
+    #[serde(default)]
+    principal: String,
+    connection_id: usize,
+    // null when a trusted proxy did not forward a readable client address
+    #[serde(default)]
+    client_address: Option<IpAddr>,
+    peer_address: SocketAddr,
+    host: String,
+    external_request: bool,
+    command_id: String,
+    outcome: AuditOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<u128>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exit_status: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output_bytes: Option<usize>,
+    #[serde(default)]
+    cached: bool,
+}
+
+// Request fields recorded with each command invocation.
+pub struct AuditRequestInfo {
+    pub request_id: String,
+    pub principal: String,
+    pub client_info: ClientInfo,
+    pub external_request: bool,
+}
+
+impl AuditRecordDTO {
+    pub fn new(
+        audit_request_info: AuditRequestInfo,
+        command_id: &CommandID,
+        run_command_result: &Result<RunCommandDTO, RunCommandError>,
+    ) -> Self {
+        let mut audit_record = Self {
+            timestamp: current_timestamp_string(),
+            request_id: audit_request_info.request_id,
+            principal: audit_request_info.principal,
+            connection_id: audit_request_info
+                .client_info
+                .client_connect_info
+                .connection_id
+                .as_usize(),
+            client_address: audit_request_info.client_info.client_ip,
+            peer_address: audit_request_info
+                .client_info
+                .client_connect_info
+                .remote_addr,
+            host: audit_request_info
+                .client_info
+                .host
+                .unwrap_or_else(|| "[Unknown]".to_owned()),
+            external_request: audit_request_info.external_request,
+            command_id: command_id.0.clone(),
+            outcome: AuditOutcome::Completed,
+            duration_ms: None,
+            exit_status: None,
+            output_bytes: None,
+            cached: false,
+        };
+
+        match run_command_result {
+            Ok(run_command_dto) => {
+                audit_record.duration_ms = Some(run_command_dto.command_duration_ms());
+                audit_record.exit_status = run_command_dto.exit_status();
+                audit_record.output_bytes = Some(run_command_dto.command_output().len());
+                audit_record.cached = run_command_dto.cached();
+            }
+            Err(RunCommandError::CommandNotFound) => {
+                audit_record.outcome = AuditOutcome::CommandNotFound;
+            }
+            Err(RunCommandError::SemaphoreAcquireError { .. }) => {
+                audit_record.outcome = AuditOutcome::QueueFull;
+            }
+            Err(RunCommandError::SandboxSetupError) => {
+                audit_record.outcome = AuditOutcome::SandboxSetupError;
+            }
+            Err(RunCommandError::AccessDenied) => {
+                audit_record.outcome = AuditOutcome::AccessDenied;
+            }
+        }
+
+        audit_record
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub limit: Option<usize>,
+    pub command_id: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum AuditLogError {
+    NotFound,
+    ReadError,
+}
+
+#[trait_variant::make(Send)]
+pub trait AuditService: Send + Sync + 'static {
+    async fn record_command(&self, audit_record: AuditRecordDTO);
+
+    async fn recent_records(
+        &self,
+        external_request: bool,
+        audit_query: AuditQuery,
+    ) -> Result<Vec<AuditRecordDTO>, AuditLogError>;
+}
+
+pub async fn new_audit_service(
+    audit_log_configuration: Option<&config::AuditLogConfiguration>,
+) -> anyhow::Result<Arc<impl AuditService>> {
+    AuditServiceImpl::new(audit_log_configuration).await
+}
+
+struct AuditLogWriter {
+    file: File,
+    file_bytes: u64,
+}
+
+struct AuditServiceImpl {
+    audit_log_configuration: Option<config::AuditLogConfiguration>,
+    writer: Mutex<Option<AuditLogWriter>>,
+}
+
+impl AuditServiceImpl {
+    async fn new(
+        audit_log_configuration: Option<&config::AuditLogConfiguration>,
+    ) -> anyhow::Result<Arc<Self>> {
+        let writer = match audit_log_configuration {
+            None => None,
+            Some(audit_log_configuration) => {
+                Some(open_audit_log(&audit_log_configuration.path).await?)
+            }
+        };
+
+        Ok(Arc::new(Self {
+            audit_log_configuration: audit_log_configuration.cloned(),
+            writer: Mutex::new(writer),
+        }))
+    }
+}
+
+async fn open_audit_log(path: &Path) -> anyhow::Result<AuditLogWriter> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("error opening audit log '{}'", path.display()))?;
+
+    let file_bytes = file
+        .metadata()
+        .await
+        .with_context(|| format!("error reading audit log metadata '{}'", path.display()))?
+        .len();
+
+    Ok(AuditLogWriter { file, file_bytes })
+}
+
+// The rotated file with index 1 is the newest.
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated_path = OsString::from(path);
+    rotated_path.push(format!(".{index}"));
+    rotated_path.into()
+}
+
+async fn rename_if_exists(from: &Path, to: &Path) -> anyhow::Result<()> {
+    match fs::rename(from, to).await {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err)
+            .with_context(|| format!("error renaming '{}' to '{}'", from.display(), to.display())),
+        _ => Ok(()),
+    }
+}
+
+async fn rotate_audit_log(
+    audit_log_configuration: &config::AuditLogConfiguration,
+) -> anyhow::Result<AuditLogWriter> {
+    let path = &audit_log_configuration.path;
+
+    if audit_log_configuration.max_rotated_files == 0 {
+        fs::remove_file(path)
+            .await
+            .with_context(|| format!("error removing '{}'", path.display()))?;
+    } else {
+        for index in (1..audit_log_configuration.max_rotated_files).rev() {
+            rename_if_exists(&rotated_path(path, index), &rotated_path(path, index + 1)).await?;
+        }
+        rename_if_exists(path, &rotated_path(path, 1)).await?;
+    }
+
+    debug!(?path, "rotated audit log");
+
+    open_audit_log(path).await
+}
+
+async fn write_audit_record(
+    audit_log_configuration: &config::AuditLogConfiguration,
+    writer: &mut AuditLogWriter,
+    audit_record: &AuditRecordDTO,
+) -> anyhow::Result<()> {
+    let mut line = serde_json::to_vec(audit_record).context("serde_json::to_vec error")?;
+    line.push(b'\n');
+
+    let line_bytes = line.len() as u64;
+
+    if writer.file_bytes > 0
+        && writer.file_bytes + line_bytes > audit_log_configuration.max_file_bytes
+    {
+        *writer = rotate_audit_log(audit_log_configuration).await?;
+    }
+
+    writer
+        .file
+        .write_all(&line)
+        .await
+        .context("audit log write error")?;
+    writer.file.flush().await.context("audit log flush error")?;
+
+    writer.file_bytes += line_bytes;
+
+    Ok(())
+}
+
+// Records from the current file and the newest rotated file, oldest first.
+async fn read_audit_records(
+    audit_log_configuration: &config::AuditLogConfiguration,
+) -> anyhow::Result<Vec<AuditRecordDTO>> {
+    let path = &audit_log_configuration.path;
+
+    let mut paths = Vec::with_capacity(2);
+    if audit_log_configuration.max_rotated_files > 0 {
+        paths.push(rotated_path(path, 1));
+    }
+    paths.push(path.clone());
+
+    let mut audit_records = Vec::new();
+
+    for path in paths {
+        let contents = match fs::read_to_string(&path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => {
+                return Err(err).with_context(|| format!("error reading '{}'", path.display()));
+            }
+        };
+
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str(line) {
+                Ok(audit_record) => audit_records.push(audit_record),
+                Err(err) => debug!(?err, ?path, "skipping invalid audit log line"),
+            }
+        }
+    }
+
+    Ok(audit_records)
+}
+
+impl AuditService for AuditServiceImpl {
+    async fn record_command(&self, audit_record: AuditRecordDTO) {
+        let Some(audit_log_configuration) = &self.audit_log_configuration else {
+            return;
+        };
+
+        let mut writer = self.writer.lock().await;
+
+        let Some(writer) = writer.as_mut() else {
+            return;
+        };
+
+        if let Err(err) = write_audit_record(audit_log_configuration, writer, &audit_record).await {
+            warn!(?err, ?audit_record, "error writing audit record");
+        }
+    }
+
+    async fn recent_records(
+        &self,
+        external_request: bool,
+        audit_query: AuditQuery,
+    ) -> Result<Vec<AuditRecordDTO>, AuditLogError> {
+        if external_request {
+            return Err(AuditLogError::NotFound);
+        }
+
+        let audit_log_configuration = self
+            .audit_log_configuration
+            .as_ref()
+            .ok_or(AuditLogError::NotFound)?;
+
+        let limit = audit_query
+            .limit
+            .unwrap_or(DEFAULT_QUERY_LIMIT)
+            .min(MAX_QUERY_LIMIT);
+
+        // hold the writer lock so the log is not rotated while reading
+        let _writer = self.writer.lock().await;
+
+        let audit_records = read_audit_records(audit_log_configuration)
+            .await
+            .map_err(|err| {
+                warn!(?err, "error reading audit log");
+                AuditLogError::ReadError
+            })?;
+
+        Ok(audit_records
+            .into_iter()
+            .rev()
+            .filter(|audit_record| {
+                audit_query
+                    .command_id
+                    .as_ref()
+                    .is_none_or(|command_id| &audit_record.command_id == command_id)
+            })
+            .take(limit)
+            .collect())
+    }
+}
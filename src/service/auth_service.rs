@@ -0,0 +1,421 @@
+use anyhow::Context;
+
+use argon2::{Argon2, PasswordHash, PasswordVerifier, password_hash::PasswordHashString};
+
+use axum::http::{HeaderMap, Uri, header};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+
+use serde::Serialize;
+
+use sha2::{Digest, Sha256};
+
+use tokio::sync::Semaphore;
+
+use tracing::{debug, warn};
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use crate::config;
+
+const AUTH_METRICS_ORDERING: Ordering = Ordering::Relaxed;
+
+// Each argon2 verify takes about 19 MiB and tens of milliseconds, more
+// concurrent basic auth requests fail instead of waiting.
+const MAX_CONCURRENT_PASSWORD_VERIFIES: usize = 2;
+
+// The authenticated caller of a request, anonymous if no valid credentials
+// were presented.
+#[derive(Clone, Debug)]
+pub struct Principal {
+    name: Option<String>,
+    roles: BTreeSet<String>,
+    admin: bool,
+    unrestricted: bool,
+    // no auth_configuration, so nobody can be an admin
+    auth_disabled: bool,
+}
+
+impl Principal {
+    fn anonymous(roles: &[String], admin_roles: &[String]) -> Self {
+        Self {
+            name: None,
+            roles: roles.iter().cloned().collect(),
+            admin: roles.iter().any(|role| admin_roles.contains(role)),
+            unrestricted: false,
+            auth_disabled: false,
+        }
+    }
+
+    fn authenticated(name: &str, roles: &[String], admin_roles: &[String]) -> Self {
+        Self {
+            name: Some(name.to_owned()),
+            roles: roles.iter().cloned().collect(),
+            admin: roles.iter().any(|role| admin_roles.contains(role)),
+            unrestricted: false,
+            auth_disabled: false,
+        }
+    }
+
+    // The operator running commands from the command line, not restricted by
+    // roles.
+    pub fn local() -> Self {
+        Self {
+            name: Some("[local]".to_owned()),
+            roles: BTreeSet::new(),
+            admin: true,
+            unrestricted: true,
+            auth_disabled: false,
+        }
+    }
+
+    fn auth_disabled() -> Self {
+        Self {
+            name: None,
+            roles: BTreeSet::new(),
+            admin: false,
+            unrestricted: false,
+            auth_disabled: true,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("[anonymous]")
+    }
+
+    fn has_any_role(&self, allowed_roles: &[String]) -> bool {
+        allowed_roles.iter().any(|role| self.roles.contains(role))
+    }
+
+    pub fn may_run(&self, command_info: &config::CommandInfo) -> bool {
+        self.unrestricted
+            || command_info.run_roles.is_empty()
+            || self.has_any_role(&command_info.run_roles)
+    }
+
+    // Whether the principal has one of the configured admin_roles.
+    pub fn is_admin(&self) -> bool {
+        self.admin
+    }
+
+    // Admins may read the configuration and status endpoints, and without
+    // auth_configuration anyone on an internal network.
+    pub fn may_view_status(&self, external_request: bool) -> bool {
+        self.admin || (self.auth_disabled && !external_request)
+    }
+
+    // Commands restricted by run_roles are hidden unless list_roles allow it.
+    pub fn may_list(&self, command_info: &config::CommandInfo) -> bool {
+        self.may_run(command_info) || self.has_any_role(&command_info.list_roles)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthStatusDTO {
+    enabled: bool,
+    authenticated_requests: usize,
+    anonymous_requests: usize,
+    authentication_failures: usize,
+    access_denied: usize,
+}
+
+#[trait_variant::make(Send)]
+pub trait AuthService: Send + Sync + 'static {
+    async fn authenticate(&self, request_headers: &HeaderMap) -> Principal;
+
+    fn record_access_denied(&self, principal: &Principal, uri: &Uri);
+
+    fn auth_status(&self) -> AuthStatusDTO;
+}
+
+pub fn new_auth_service(
+    auth_configuration: Option<&config::AuthConfiguration>,
+) -> anyhow::Result<Arc<impl AuthService>> {
+    AuthServiceImpl::new(auth_configuration)
+}
+
+struct ApiToken {
+    name: String,
+    roles: Vec<String>,
+}
+
+struct BasicAuthUser {
+    password_hash: PasswordHashString,
+    roles: Vec<String>,
+}
+
+#[derive(Default)]
+struct AuthCounterMetrics {
+    authenticated_requests: AtomicUsize,
+    anonymous_requests: AtomicUsize,
+    authentication_failures: AtomicUsize,
+    access_denied: AtomicUsize,
+}
+
+struct AuthServiceImpl {
+    enabled: bool,
+    anonymous_roles: Vec<String>,
+    admin_roles: Vec<String>,
+    token_sha256_to_api_token: HashMap<String, ApiToken>,
+    username_to_user: HashMap<String, BasicAuthUser>,
+    // verified for unknown usernames so response times do not tell which
+    // usernames exist, none without users
+    dummy_password_hash: Option<PasswordHashString>,
+    password_verify_semaphore: Arc<Semaphore>,
+    counter_metrics: AuthCounterMetrics,
+}
+
+enum AuthenticationError {
+    UnknownScheme,
+    InvalidToken,
+    InvalidBasicCredentials,
+    TooManyPasswordVerifies,
+}
+
+fn sha256_hex(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+impl AuthServiceImpl {
+    fn new(auth_configuration: Option<&config::AuthConfiguration>) -> anyhow::Result<Arc<Self>> {
+        let Some(auth_configuration) = auth_configuration else {
+            return Ok(Arc::new(Self {
+                enabled: false,
+                anonymous_roles: Vec::new(),
+                admin_roles: Vec::new(),
+                token_sha256_to_api_token: HashMap::new(),
+                username_to_user: HashMap::new(),
+                dummy_password_hash: None,
+                password_verify_semaphore: Arc::new(Semaphore::new(
+                    MAX_CONCURRENT_PASSWORD_VERIFIES,
+                )),
+                counter_metrics: AuthCounterMetrics::default(),
+            }));
+        };
+
+        let token_sha256_to_api_token = auth_configuration
+            .api_tokens
+            .iter()
+            .map(|api_token| {
+                let token_sha256 = api_token.token_sha256.to_ascii_lowercase();
+                if token_sha256.len() != 64 || !token_sha256.chars().all(|c| c.is_ascii_hexdigit())
+                {
+                    anyhow::bail!(
+                        "api token '{}' token_sha256 must be 64 hex characters",
+                        api_token.name
+                    );
+                }
+                Ok((
+                    token_sha256,
+                    ApiToken {
+                        name: api_token.name.clone(),
+                        roles: api_token.roles.clone(),
+                    },
+                ))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let username_to_user = auth_configuration
+            .users
+            .iter()
+            .map(|user| {
+                let password_hash = PasswordHash::new(&user.password_hash)
+                    .map_err(|err| anyhow::anyhow!("{err}"))
+                    .with_context(|| {
+                        format!("invalid password_hash for user '{}'", user.username)
+                    })?;
+                Ok((
+                    user.username.clone(),
+                    BasicAuthUser {
+                        password_hash: password_hash.serialize(),
+                        roles: user.roles.clone(),
+                    },
+                ))
+            })
+            .collect::<anyhow::Result<HashMap<_, _>>>()?;
+
+        // a configured hash has the same cost parameters as the real ones
+        let dummy_password_hash = username_to_user
+            .values()
+            .next()
+            .map(|user| user.password_hash.clone());
+
+        Ok(Arc::new(Self {
+            enabled: true,
+            anonymous_roles: auth_configuration.anonymous_roles.clone(),
+            admin_roles: auth_configuration.admin_roles.clone(),
+            token_sha256_to_api_token,
+            username_to_user,
+            dummy_password_hash,
+            password_verify_semaphore: Arc::new(Semaphore::new(MAX_CONCURRENT_PASSWORD_VERIFIES)),
+            counter_metrics: AuthCounterMetrics::default(),
+        }))
+    }
+
+    fn authenticate_token(&self, token: &str) -> Result<Principal, AuthenticationError> {
+        let api_token = self
+            .token_sha256_to_api_token
+            .get(&sha256_hex(token))
+            .ok_or(AuthenticationError::InvalidToken)?;
+
+        Ok(Principal::authenticated(
+            &api_token.name,
+            &api_token.roles,
+            &self.admin_roles,
+        ))
+    }
+
+    async fn authenticate_basic(
+        &self,
+        credentials: &str,
+    ) -> Result<Principal, AuthenticationError> {
+        let decoded = BASE64_STANDARD
+            .decode(credentials)
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .ok_or(AuthenticationError::InvalidBasicCredentials)?;
+
+        let (username, password) = decoded
+            .split_once(':')
+            .ok_or(AuthenticationError::InvalidBasicCredentials)?;
+
+        let user = self.username_to_user.get_key_value(username);
+
+        let password_hash = user
+            .map(|(_, user)| &user.password_hash)
+            .or(self.dummy_password_hash.as_ref())
+            .ok_or(AuthenticationError::InvalidBasicCredentials)?
+            .clone();
+
+        // the permit moves into the blocking task, so it is held until the
+        // verify ends even if the request is dropped
+        let permit = Arc::clone(&self.password_verify_semaphore)
+            .try_acquire_owned()
+            .map_err(|_| AuthenticationError::TooManyPasswordVerifies)?;
+
+        // argon2 is deliberately expensive, keep it off the async worker threads
+        let password = password.to_owned();
+        let verified = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            Argon2::default()
+                .verify_password(password.as_bytes(), &password_hash.password_hash())
+                .is_ok()
+        })
+        .await
+        .unwrap_or(false);
+
+        let Some((username, user)) = user.filter(|_| verified) else {
+            return Err(AuthenticationError::InvalidBasicCredentials);
+        };
+
+        Ok(Principal::authenticated(
+            username,
+            &user.roles,
+            &self.admin_roles,
+        ))
+    }
+
+    async fn authenticate_authorization(
+        &self,
+        authorization: &str,
+    ) -> Result<Principal, AuthenticationError> {
+        let (scheme, credentials) = authorization
+            .split_once(' ')
+            .ok_or(AuthenticationError::UnknownScheme)?;
+
+        let credentials = credentials.trim();
+
+        if scheme.eq_ignore_ascii_case("bearer") {
+            self.authenticate_token(credentials)
+        } else if scheme.eq_ignore_ascii_case("basic") {
+            self.authenticate_basic(credentials).await
+        } else {
+            Err(AuthenticationError::UnknownScheme)
+        }
+    }
+}
+
+impl AuthService for AuthServiceImpl {
+    async fn authenticate(&self, request_headers: &HeaderMap) -> Principal {
+        if !self.enabled {
+            return Principal::auth_disabled();
+        }
+
+        let anonymous = Principal::anonymous(&self.anonymous_roles, &self.admin_roles);
+
+        let Some(authorization) = request_headers.get(header::AUTHORIZATION) else {
+            self.counter_metrics
+                .anonymous_requests
+                .fetch_add(1, AUTH_METRICS_ORDERING);
+            return anonymous;
+        };
+
+        let result = match authorization.to_str() {
+            Ok(authorization) => self.authenticate_authorization(authorization).await,
+            Err(_) => Err(AuthenticationError::UnknownScheme),
+        };
+
+        match result {
+            Ok(principal) => {
+                debug!(principal = principal.name(), "authenticated request");
+                self.counter_metrics
+                    .authenticated_requests
+                    .fetch_add(1, AUTH_METRICS_ORDERING);
+                principal
+            }
+            Err(err) => {
+                let reason = match err {
+                    AuthenticationError::UnknownScheme => "unknown authorization scheme",
+                    AuthenticationError::InvalidToken => "invalid api token",
+                    AuthenticationError::InvalidBasicCredentials => "invalid basic credentials",
+                    AuthenticationError::TooManyPasswordVerifies => {
+                        "too many concurrent password verifies"
+                    }
+                };
+                warn!(reason, "authentication failed");
+                self.counter_metrics
+                    .authentication_failures
+                    .fetch_add(1, AUTH_METRICS_ORDERING);
+                anonymous
+            }
+        }
+    }
+
+    fn record_access_denied(&self, principal: &Principal, uri: &Uri) {
+        warn!(principal = principal.name(), %uri, "access denied");
+        self.counter_metrics
+            .access_denied
+            .fetch_add(1, AUTH_METRICS_ORDERING);
+    }
+
+    fn auth_status(&self) -> AuthStatusDTO {
+        AuthStatusDTO {
+            enabled: self.enabled,
+            authenticated_requests: self
+                .counter_metrics
+                .authenticated_requests
+                .load(AUTH_METRICS_ORDERING),
+            anonymous_requests: self
+                .counter_metrics
+                .anonymous_requests
+                .load(AUTH_METRICS_ORDERING),
+            authentication_failures: self
+                .counter_metrics
+                .authentication_failures
+                .load(AUTH_METRICS_ORDERING),
+            access_denied: self
+                .counter_metrics
+                .access_denied
+                .load(AUTH_METRICS_ORDERING),
+        }
+    }
+}
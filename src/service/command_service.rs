@@ -1,182 +1,992 @@
+mod builtin;
+mod cache;
+mod diff;
+mod execution;
+mod parser;
+mod process;
+mod queue;
+#[cfg(target_os = "linux")]
+mod sandbox;
+mod schedule;
+mod validation;
+
+use anyhow::Context;
+
+use arc_swap::ArcSwap;
+
 use itertools::Itertools;
 
 use serde::Serialize;
 
-use std::{collections::HashMap, process::Stdio, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Arc, Mutex},
+};
 
 use tokio::{
-    process::Command,
-    sync::{Semaphore, SemaphorePermit},
+    sync::SemaphorePermit,
+    task::JoinSet,
     time::{Duration, Instant},
 };
 
 use tracing::warn;
 
-use crate::{config, utils::time::current_timestamp_string};
+use crate::{config, service::auth_service::Principal, utils::time::current_timestamp_string};
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Ord, PartialOrd)]
 pub struct CommandID(pub String);
 
 #[trait_variant::make(Send)]
 pub trait CommandsService: Send + Sync + 'static {
-    fn all_commands(&self, external_request: bool) -> Vec<CommandInfoDTO>;
+    fn all_commands(&self, external_request: bool, principal: &Principal) -> Vec<CommandInfoDTO>;
 
     async fn run_command(
         &self,
         external_request: bool,
+        principal: &Principal,
         command_id: CommandID,
     ) -> Result<RunCommandDTO, RunCommandError>;
+
+    fn command_history(
+        &self,
+        external_request: bool,
+        principal: &Principal,
+        command_id: CommandID,
+    ) -> Result<CommandHistoryDTO, RunCommandError>;
+
+    fn diff_previous_run(&self, run_command_dto: RunCommandDTO) -> RunCommandDTO;
+
+    fn commands_status(&self, external_request: bool, principal: &Principal) -> CommandsStatusDTO;
+
+    async fn self_test(&self) -> SelfTestReportDTO;
+
+    // Starts running scheduled commands, also after reloads. Only servers
+    // call this, so other uses of the service do not run schedules.
+    fn start_schedules(&self);
+
+    // Validates and applies the command configuration of a reloaded
+    // configuration.
+    fn reload(&self, configuration: Arc<config::Configuration>) -> anyhow::Result<()>;
 }
 
 #[derive(Clone, Debug, Serialize)]
 pub struct CommandInfoDTO {
-    pub id: &'static String,
-    pub description: &'static String,
-    pub command: &'static String,
-    pub args: &'static Vec<String>,
+    pub id: String,
+    pub description: String,
+    pub kind: config::CommandKind,
+    pub command: String,
+    pub args: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<config::CommandStep>,
 }
 
-impl From<&'static config::CommandInfo> for CommandInfoDTO {
-    fn from(command_info: &'static config::CommandInfo) -> Self {
+impl From<&config::CommandInfo> for CommandInfoDTO {
+    fn from(command_info: &config::CommandInfo) -> Self {
         Self {
-            id: &command_info.id,
-            description: &command_info.description,
-            command: &command_info.command,
-            args: &command_info.args,
+            id: command_info.id.clone(),
+            description: command_info.description.clone(),
+            kind: command_info.kind.clone(),
+            command: command_info.command.clone(),
+            args: command_info.args.clone(),
+            steps: command_info.steps.clone(),
         }
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
+pub struct CommandStepDTO {
+    command: String,
+    args: Vec<String>,
+    command_duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_status: Option<i32>,
+    command_output: String,
+}
+
+impl CommandStepDTO {
+    pub fn new(
+        command: String,
+        args: Vec<String>,
+        command_duration: Duration,
+        exit_status: Option<i32>,
+        command_output: String,
+    ) -> Self {
+        Self {
+            command,
+            args,
+            command_duration_ms: command_duration.as_millis(),
+            exit_status,
+            command_output,
+        }
+    }
+}
+
+impl From<&process::ProcessRun<'_>> for CommandStepDTO {
+    fn from(process_run: &process::ProcessRun<'_>) -> Self {
+        Self {
+            command: process_run.command.to_owned(),
+            args: process_run.args.to_vec(),
+            command_duration_ms: process_run.duration.as_millis(),
+            exit_status: process_run.exit_status(),
+            command_output: process_run.combined_output(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
 pub struct RunCommandDTO {
     now: String,
     command_duration_ms: u128,
     command_info: CommandInfoDTO,
     command_output: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    steps: Vec<CommandStepDTO>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parsed: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parse_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_status: Option<i32>,
+    cached: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_age_ms: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<diff::CommandOutputDiffDTO>,
+    // the run this one replaced as the latest, kept by cached copies
+    #[serde(skip)]
+    previous_run: Option<Arc<diff::RunSnapshot>>,
+}
+
+impl RunCommandDTO {
+    // A run finished now, for CommandsService implementations outside this
+    // crate. The with_ methods set the optional results.
+    pub fn new(
+        command_info: CommandInfoDTO,
+        command_duration: Duration,
+        command_output: String,
+    ) -> Self {
+        Self {
+            now: current_timestamp_string(),
+            command_duration_ms: command_duration.as_millis(),
+            command_info,
+            command_output,
+            steps: Vec::new(),
+            parsed: None,
+            parse_error: None,
+            exit_status: None,
+            cached: false,
+            cache_age_ms: None,
+            diff: None,
+            previous_run: None,
+        }
+    }
+
+    pub fn with_exit_status(self, exit_status: i32) -> Self {
+        Self {
+            exit_status: Some(exit_status),
+            ..self
+        }
+    }
+
+    pub fn with_steps(self, steps: Vec<CommandStepDTO>) -> Self {
+        Self { steps, ..self }
+    }
+
+    pub fn with_parsed(self, parsed: serde_json::Value) -> Self {
+        Self {
+            parsed: Some(parsed),
+            ..self
+        }
+    }
+
+    pub fn with_parse_error(self, parse_error: String) -> Self {
+        Self {
+            parse_error: Some(parse_error),
+            ..self
+        }
+    }
+
+    fn cached_copy(&self, cache_age: Duration) -> Self {
+        Self {
+            cached: true,
+            cache_age_ms: Some(cache_age.as_millis()),
+            ..self.clone()
+        }
+    }
+
+    pub fn command_id(&self) -> &str {
+        &self.command_info.id
+    }
+
+    pub fn now(&self) -> &str {
+        &self.now
+    }
+
+    pub fn command_duration_ms(&self) -> u128 {
+        self.command_duration_ms
+    }
+
+    pub fn exit_status(&self) -> Option<i32> {
+        self.exit_status
+    }
+
+    pub fn command_output(&self) -> &str {
+        &self.command_output
+    }
+
+    pub fn unified_diff(&self) -> Option<&str> {
+        self.diff
+            .as_ref()
+            .map(diff::CommandOutputDiffDTO::unified_diff)
+    }
+
+    pub fn cached(&self) -> bool {
+        self.cached
+    }
+
+    // Builtins that fail have no parsed output.
+    pub fn succeeded(&self) -> bool {
+        match self.command_info.kind {
+            config::CommandKind::Builtin => self.parsed.is_some(),
+            _ => self.exit_status == Some(0),
+        }
+    }
+
+    pub fn cache_age(&self) -> Option<Duration> {
+        self.cache_age_ms
+            .map(|cache_age_ms| Duration::from_millis(cache_age_ms.try_into().unwrap_or(u64::MAX)))
+    }
+
+    // Identifies one command execution, so cached copies share the same value.
+    pub fn etag(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.command_info.id.hash(&mut hasher);
+        self.now.hash(&mut hasher);
+        self.command_output.hash(&mut hasher);
+        self.diff.is_some().hash(&mut hasher);
+        format!("\"{:016x}\"", hasher.finish())
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CommandHistoryEntryDTO {
+    now: String,
+    command_duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_status: Option<i32>,
+    command_output: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parsed: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parse_error: Option<String>,
+}
+
+impl From<RunCommandDTO> for CommandHistoryEntryDTO {
+    fn from(run_command_dto: RunCommandDTO) -> Self {
+        Self {
+            now: run_command_dto.now,
+            command_duration_ms: run_command_dto.command_duration_ms,
+            exit_status: run_command_dto.exit_status,
+            command_output: run_command_dto.command_output,
+            parsed: run_command_dto.parsed,
+            parse_error: run_command_dto.parse_error,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CommandHistoryDTO {
+    command_info: CommandInfoDTO,
+    schedule: String,
+    history: Vec<CommandHistoryEntryDTO>,
+}
+
+impl CommandHistoryDTO {
+    pub fn new(
+        command_info: CommandInfoDTO,
+        schedule: String,
+        history: Vec<CommandHistoryEntryDTO>,
+    ) -> Self {
+        Self {
+            command_info,
+            schedule,
+            history,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CommandQueueStatusDTO {
+    max_permits: usize,
+    permits_in_use: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_queue_depth: Option<usize>,
+    queue_depth: usize,
+}
+
+impl CommandQueueStatusDTO {
+    pub fn new(
+        max_permits: usize,
+        permits_in_use: usize,
+        max_queue_depth: Option<usize>,
+        queue_depth: usize,
+    ) -> Self {
+        Self {
+            max_permits,
+            permits_in_use,
+            max_queue_depth,
+            queue_depth,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CommandsStatusDTO {
+    global: CommandQueueStatusDTO,
+    commands: BTreeMap<String, CommandQueueStatusDTO>,
+}
+
+impl CommandsStatusDTO {
+    pub fn new(
+        global: CommandQueueStatusDTO,
+        commands: BTreeMap<String, CommandQueueStatusDTO>,
+    ) -> Self {
+        Self { global, commands }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SelfTestResultDTO {
+    id: String,
+    passed: bool,
+    command_duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_status: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl SelfTestResultDTO {
+    pub fn new(
+        id: String,
+        passed: bool,
+        command_duration: Duration,
+        exit_status: Option<i32>,
+        error: Option<String>,
+    ) -> Self {
+        Self {
+            id,
+            passed,
+            command_duration_ms: command_duration.as_millis(),
+            exit_status,
+            error,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SelfTestReportDTO {
+    passed: usize,
+    failed: usize,
+    results: Vec<SelfTestResultDTO>,
+}
+
+impl SelfTestReportDTO {
+    pub fn new(results: Vec<SelfTestResultDTO>) -> Self {
+        let passed = results.iter().filter(|result| result.passed).count();
+
+        Self {
+            passed,
+            failed: results.len() - passed,
+            results,
+        }
+    }
+
+    pub fn all_passed(&self) -> bool {
+        self.failed == 0
+    }
 }
 
 #[derive(Debug)]
 pub enum RunCommandError {
     CommandNotFound,
-    SemaphoreAcquireError,
+    SemaphoreAcquireError {
+        queue_position: usize,
+        retry_after: Duration,
+    },
+    SandboxSetupError,
+    AccessDenied,
 }
 
-pub fn new_commands_service() -> Arc<impl CommandsService> {
-    CommandsServiceImpl::new()
+pub fn new_commands_service(
+    configuration: Arc<config::Configuration>,
+) -> anyhow::Result<Arc<impl CommandsService>> {
+    CommandsServiceImpl::new(configuration)
 }
 
-struct CommandsServiceImpl {
-    all_command_info: Vec<CommandInfoDTO>,
-    external_command_info: Vec<CommandInfoDTO>,
-    id_to_command_info: HashMap<CommandID, &'static config::CommandInfo>,
-    semapore: Semaphore,
+pub fn validate_command_configuration(
+    command_configuration: &config::CommandConfiguration,
+) -> anyhow::Result<()> {
+    validation::validate_command_configuration(command_configuration)
+}
+
+pub fn exec_command_launcher_if_requested() {
+    execution::exec_launcher_if_requested();
+}
+
+// Everything built from the command configuration, replaced as a whole when
+// the configuration is reloaded. Cached results, histories and recent runs of
+// commands a reload leaves unchanged are shared with the new state. Requests
+// in flight keep the state they started with, so old and new queues may
+// briefly both hold permits after a reload.
+struct CommandsState {
+    configuration: Arc<config::Configuration>,
+    // index in command_configuration.commands
+    id_to_command_info: HashMap<CommandID, usize>,
+    id_to_command_result_cache: HashMap<CommandID, Arc<cache::CommandResultCache>>,
+    id_to_command_history: HashMap<CommandID, Arc<schedule::CommandHistory>>,
+    id_to_recent_runs: HashMap<CommandID, Arc<diff::RecentRuns>>,
+    id_to_command_queue: HashMap<CommandID, queue::CommandQueue>,
+    global_command_queue: queue::CommandQueue,
     semapore_acquire_timeout: Duration,
+    self_test_timeout: Duration,
+    builtin_paths: builtin::BuiltinPaths,
+}
+
+// Permits are released when the command completes.
+struct CommandPermits<'a> {
+    _command_permit: Option<SemaphorePermit<'a>>,
+    _global_permit: SemaphorePermit<'a>,
+}
+
+struct CommandsServiceImpl {
+    state: ArcSwap<CommandsState>,
+    // none until start_schedules is called
+    schedule_tasks: Mutex<Option<JoinSet<()>>>,
 }
 
 impl CommandsServiceImpl {
-    fn new() -> Arc<Self> {
-        let command_configuration = &config::instance().command_configuration;
+    fn new(configuration: Arc<config::Configuration>) -> anyhow::Result<Arc<Self>> {
+        let state = Arc::new(CommandsState::new(configuration, None)?);
+
+        Ok(Arc::new(Self {
+            state: ArcSwap::new(state),
+            schedule_tasks: Mutex::new(None),
+        }))
+    }
+
+    fn state(&self) -> Arc<CommandsState> {
+        self.state.load_full()
+    }
+}
+
+impl CommandsState {
+    // previous is the state replaced by a reload
+    fn new(
+        configuration: Arc<config::Configuration>,
+        previous: Option<&CommandsState>,
+    ) -> anyhow::Result<Self> {
+        let command_configuration = &configuration.command_configuration;
 
-        Arc::new(Self {
-            all_command_info: command_configuration.commands.iter().map_into().collect(),
-            external_command_info: command_configuration
+        validation::validate_command_configuration(command_configuration)?;
+
+        let unchanged = |command_config: &config::CommandInfo| {
+            previous.filter(|previous| {
+                previous.command_info(&CommandID(command_config.id.clone())) == Some(command_config)
+            })
+        };
+
+        Ok(Self {
+            id_to_command_info: command_configuration
                 .commands
                 .iter()
-                .filter(|ci| !ci.internal_only)
-                .map_into()
+                .enumerate()
+                .map(|(index, command_config)| (CommandID(command_config.id.clone()), index))
                 .collect(),
-            id_to_command_info: command_configuration
+            id_to_command_result_cache: command_configuration
+                .commands
+                .iter()
+                .filter_map(|command_config| {
+                    let command_id = CommandID(command_config.id.clone());
+                    let command_result_cache = match unchanged(command_config) {
+                        Some(previous) => {
+                            Arc::clone(previous.id_to_command_result_cache.get(&command_id)?)
+                        }
+                        None => Arc::new(cache::CommandResultCache::new(command_config.cache_ttl?)),
+                    };
+                    Some((command_id, command_result_cache))
+                })
+                .collect(),
+            id_to_command_history: command_configuration
+                .commands
+                .iter()
+                .filter_map(|command_config| {
+                    let command_id = CommandID(command_config.id.clone());
+                    let command_history = match unchanged(command_config) {
+                        Some(previous) => {
+                            Arc::clone(previous.id_to_command_history.get(&command_id)?)
+                        }
+                        None => Arc::new(schedule::CommandHistory::new(command_config)?),
+                    };
+                    Some((command_id, command_history))
+                })
+                .collect(),
+            id_to_recent_runs: command_configuration
                 .commands
                 .iter()
-                .map(|command_config| (CommandID(command_config.id.clone()), command_config))
+                .map(|command_config| {
+                    let command_id = CommandID(command_config.id.clone());
+                    let recent_runs = unchanged(command_config)
+                        .and_then(|previous| previous.id_to_recent_runs.get(&command_id))
+                        .map_or_else(Arc::default, Arc::clone);
+                    (command_id, recent_runs)
+                })
                 .collect(),
-            semapore: Semaphore::new(command_configuration.max_concurrent_commands),
+            id_to_command_queue: command_configuration
+                .commands
+                .iter()
+                .filter_map(|command_config| {
+                    command_config.max_concurrent.map(|max_concurrent| {
+                        (
+                            CommandID(command_config.id.clone()),
+                            queue::CommandQueue::new(
+                                max_concurrent,
+                                command_configuration.max_queue_depth,
+                            ),
+                        )
+                    })
+                })
+                .collect(),
+            global_command_queue: queue::CommandQueue::new(
+                command_configuration.max_concurrent_commands,
+                command_configuration.max_queue_depth,
+            ),
             semapore_acquire_timeout: command_configuration.semaphore_acquire_timeout,
+            self_test_timeout: command_configuration.self_test_timeout,
+            builtin_paths: builtin::BuiltinPaths::from(command_configuration),
+            configuration,
         })
     }
 
-    async fn acquire_semaphore(&self) -> Result<SemaphorePermit<'_>, RunCommandError> {
-        let result = tokio::time::timeout(self.semapore_acquire_timeout, self.semapore.acquire())
-            .await
-            .map_err(|error| {
-                warn!(?error, "acquire_semapore timeout error");
-                RunCommandError::SemaphoreAcquireError
-            })?;
+    fn command_configuration(&self) -> &config::CommandConfiguration {
+        &self.configuration.command_configuration
+    }
 
-        let permit = result.map_err(|error| {
-            warn!(?error, "acquire_semapore acquire error");
-            RunCommandError::SemaphoreAcquireError
-        })?;
+    fn command_info(&self, command_id: &CommandID) -> Option<&config::CommandInfo> {
+        let index = *self.id_to_command_info.get(command_id)?;
 
-        Ok(permit)
+        self.command_configuration().commands.get(index)
     }
 
-    async fn internal_run_command(
+    fn lookup_command_info(
+        &self,
+        external_request: bool,
+        command_id: &CommandID,
+    ) -> Result<&config::CommandInfo, RunCommandError> {
+        let command_info = self
+            .command_info(command_id)
+            .ok_or(RunCommandError::CommandNotFound)?;
+
+        if command_info.internal_only && external_request {
+            warn!(
+                ?command_id,
+                "got external request for internal_only command",
+            );
+            return Err(RunCommandError::CommandNotFound);
+        }
+
+        Ok(command_info)
+    }
+
+    fn lookup_runnable_command_info(
+        &self,
+        external_request: bool,
+        principal: &Principal,
+        command_id: &CommandID,
+    ) -> Result<&config::CommandInfo, RunCommandError> {
+        let command_info = self.lookup_command_info(external_request, command_id)?;
+
+        if !principal.may_run(command_info) {
+            return Err(RunCommandError::AccessDenied);
+        }
+
+        Ok(command_info)
+    }
+
+    fn semaphore_acquire_error(&self, queue_position: usize) -> RunCommandError {
+        // Round up to whole seconds for the Retry-After header.
+        let retry_after =
+            Duration::from_secs(self.semapore_acquire_timeout.as_secs_f64().ceil() as u64)
+                .max(Duration::from_secs(1));
+
+        RunCommandError::SemaphoreAcquireError {
+            queue_position,
+            retry_after,
+        }
+    }
+
+    // Acquire the per-command permit first so that waiters for a slow command
+    // queue behind that command instead of holding global permits.
+    async fn acquire_permits(
         &self,
-        command_info: &'static config::CommandInfo,
-        permit: SemaphorePermit<'_>,
-    ) -> RunCommandDTO {
+        command_id: &CommandID,
+    ) -> Result<CommandPermits<'_>, RunCommandError> {
+        let deadline = Instant::now() + self.semapore_acquire_timeout;
+
+        let command_permit = match self.id_to_command_queue.get(command_id) {
+            None => None,
+            Some(command_queue) => Some(
+                command_queue
+                    .acquire(deadline)
+                    .await
+                    .map_err(|queue_position| self.semaphore_acquire_error(queue_position))?,
+            ),
+        };
+
+        let global_permit = self
+            .global_command_queue
+            .acquire(deadline)
+            .await
+            .map_err(|queue_position| self.semaphore_acquire_error(queue_position))?;
+
+        Ok(CommandPermits {
+            _command_permit: command_permit,
+            _global_permit: global_permit,
+        })
+    }
+
+    // Builtin commands run in process without acquiring permits.
+    async fn execute_command(
+        &self,
+        command_id: &CommandID,
+        command_info: &config::CommandInfo,
+    ) -> Result<RunCommandDTO, RunCommandError> {
+        let mut run_command_dto = match command_info.kind {
+            config::CommandKind::Builtin => self.run_builtin_command(command_info).await,
+            config::CommandKind::External
+            | config::CommandKind::Steps
+            | config::CommandKind::Pipeline => {
+                let permits = self.acquire_permits(command_id).await?;
+
+                self.internal_run_command(command_info, permits).await?
+            }
+        };
+
+        if let Some(recent_runs) = self.id_to_recent_runs.get(command_id) {
+            recent_runs.add_run(&mut run_command_dto);
+        }
+
+        Ok(run_command_dto)
+    }
+
+    async fn run_builtin_command(&self, command_info: &config::CommandInfo) -> RunCommandDTO {
+        let name = command_info.command.clone();
+        let args = command_info.args.clone();
+        let builtin_paths = self.builtin_paths.clone();
+
         let command_start_time = Instant::now();
-        let command_result = Command::new(&command_info.command)
-            .args(&command_info.args)
-            .kill_on_drop(true)
-            .stdin(Stdio::null())
-            .output()
-            .await;
+        let builtin_result =
+            tokio::task::spawn_blocking(move || builtin::run_builtin(&name, &args, &builtin_paths))
+                .await
+                .context("spawn_blocking error")
+                .and_then(|builtin_result| builtin_result);
         let command_duration = command_start_time.elapsed();
 
-        drop(permit);
+        let (command_output, parsed) = match builtin_result {
+            Err(err) => (format!("error running builtin command {err:#}"), None),
+            Ok(builtin_output) => (builtin_output.text, Some(builtin_output.parsed)),
+        };
 
         RunCommandDTO {
             now: current_timestamp_string(),
             command_duration_ms: command_duration.as_millis(),
             command_info: command_info.into(),
-            command_output: match command_result {
-                Err(err) => {
-                    format!("error running command {err}")
-                }
-                Ok(command_output) => {
-                    let mut combined_output = String::with_capacity(
-                        command_output.stderr.len() + command_output.stdout.len(),
-                    );
-                    combined_output.push_str(&String::from_utf8_lossy(&command_output.stderr));
-                    combined_output.push_str(&String::from_utf8_lossy(&command_output.stdout));
-                    combined_output
-                }
-            },
+            command_output,
+            steps: Vec::new(),
+            parsed,
+            parse_error: None,
+            exit_status: None,
+            cached: false,
+            cache_age_ms: None,
+            diff: None,
+            previous_run: None,
         }
     }
-}
 
-impl CommandsService for CommandsServiceImpl {
-    fn all_commands(&self, external_request: bool) -> Vec<CommandInfoDTO> {
-        if external_request {
-            self.external_command_info.clone()
-        } else {
-            self.all_command_info.clone()
+    async fn internal_run_command(
+        &self,
+        command_info: &config::CommandInfo,
+        permits: CommandPermits<'_>,
+    ) -> Result<RunCommandDTO, RunCommandError> {
+        let sandbox_profile = match &command_info.execution.sandbox_profile {
+            None => None,
+            Some(sandbox_profile_name) => Some(
+                self.command_configuration()
+                    .sandbox_profiles
+                    .get(sandbox_profile_name)
+                    .ok_or_else(|| {
+                        warn!(sandbox_profile_name, "sandbox profile not found");
+                        RunCommandError::SandboxSetupError
+                    })?,
+            ),
+        };
+
+        let command_start_time = Instant::now();
+        let process_runs = match command_info.kind {
+            config::CommandKind::Steps => process::run_steps(command_info, sandbox_profile).await,
+            config::CommandKind::Pipeline => {
+                process::run_pipeline(command_info, sandbox_profile).await
+            }
+            config::CommandKind::External | config::CommandKind::Builtin => {
+                vec![
+                    process::run_process(
+                        &command_info.command,
+                        &command_info.args,
+                        &command_info.execution,
+                        sandbox_profile,
+                    )
+                    .await,
+                ]
+            }
+        };
+        let command_duration = command_start_time.elapsed();
+
+        drop(permits);
+
+        if let Some(error) = process_runs.iter().find_map(|process_run| {
+            process_run
+                .output
+                .as_ref()
+                .ok()
+                .and_then(execution::sandbox_setup_error)
+        }) {
+            warn!(command_info.id, error, "command sandbox setup error");
+            return Err(RunCommandError::SandboxSetupError);
         }
+
+        let exit_status = process::overall_exit_status(&process_runs);
+
+        // the final process's stdout is parsed
+        let parse_result = command_info.parser.as_ref().and_then(|output_parser| {
+            let stdout = process_runs.last()?.stdout()?;
+            Some(parser::parse_output(
+                output_parser,
+                &String::from_utf8_lossy(stdout),
+            ))
+        });
+
+        let (parsed, parse_error) = match parse_result {
+            None => (None, None),
+            Some(Ok(parsed)) => (Some(parsed), None),
+            Some(Err(err)) => {
+                warn!(command_info.id, ?err, "command output parse error");
+                (None, Some(format!("{err:#}")))
+            }
+        };
+
+        let (command_output, steps) = match command_info.kind {
+            config::CommandKind::Steps | config::CommandKind::Pipeline => (
+                process_runs
+                    .iter()
+                    .map(process::ProcessRun::combined_output)
+                    .collect(),
+                process_runs.iter().map_into().collect(),
+            ),
+            config::CommandKind::External | config::CommandKind::Builtin => (
+                process_runs
+                    .first()
+                    .map(process::ProcessRun::combined_output)
+                    .unwrap_or_default(),
+                Vec::new(),
+            ),
+        };
+
+        Ok(RunCommandDTO {
+            now: current_timestamp_string(),
+            command_duration_ms: command_duration.as_millis(),
+            command_info: command_info.into(),
+            exit_status,
+            command_output,
+            steps,
+            parsed,
+            parse_error,
+            cached: false,
+            cache_age_ms: None,
+            diff: None,
+            previous_run: None,
+        })
+    }
+
+    fn all_commands(&self, external_request: bool, principal: &Principal) -> Vec<CommandInfoDTO> {
+        self.command_configuration()
+            .commands
+            .iter()
+            .filter(|ci| !(ci.internal_only && external_request) && principal.may_list(ci))
+            .map_into()
+            .collect()
     }
 
     async fn run_command(
         &self,
         external_request: bool,
+        principal: &Principal,
         command_id: CommandID,
     ) -> Result<RunCommandDTO, RunCommandError> {
-        let command_info = self
-            .id_to_command_info
+        let command_info =
+            self.lookup_runnable_command_info(external_request, principal, &command_id)?;
+
+        let run = self.execute_command(&command_id, command_info);
+
+        match self.id_to_command_result_cache.get(&command_id) {
+            Some(command_result_cache) => command_result_cache.get_or_run(run).await,
+            None => run.await,
+        }
+    }
+
+    fn command_history(
+        &self,
+        external_request: bool,
+        principal: &Principal,
+        command_id: CommandID,
+    ) -> Result<CommandHistoryDTO, RunCommandError> {
+        let command_info =
+            self.lookup_runnable_command_info(external_request, principal, &command_id)?;
+
+        let command_history = self
+            .id_to_command_history
             .get(&command_id)
             .ok_or(RunCommandError::CommandNotFound)?;
 
-        if command_info.internal_only && external_request {
-            warn!(
-                ?command_id,
-                "got external request for internal_only command",
+        Ok(CommandHistoryDTO::new(
+            command_info.into(),
+            command_history.trigger.to_string(),
+            command_history.entries(),
+        ))
+    }
+
+    fn diff_previous_run(&self, run_command_dto: RunCommandDTO) -> RunCommandDTO {
+        RunCommandDTO {
+            diff: Some(diff::diff_previous(&run_command_dto)),
+            ..run_command_dto
+        }
+    }
+
+    fn commands_status(&self, external_request: bool, principal: &Principal) -> CommandsStatusDTO {
+        CommandsStatusDTO::new(
+            self.global_command_queue.status_dto(),
+            self.id_to_command_queue
+                .iter()
+                .filter_map(|(command_id, command_queue)| {
+                    let command_info = self
+                        .lookup_command_info(external_request, command_id)
+                        .ok()
+                        .filter(|command_info| principal.may_list(command_info))?;
+                    Some((command_info.id.clone(), command_queue.status_dto()))
+                })
+                .collect(),
+        )
+    }
+
+    async fn self_test(&self) -> SelfTestReportDTO {
+        let commands = &self.command_configuration().commands;
+
+        let mut results = Vec::with_capacity(commands.len());
+
+        for command_info in commands {
+            let command_id = CommandID(command_info.id.clone());
+
+            let command_start_time = Instant::now();
+            let run_result = tokio::time::timeout(
+                self.self_test_timeout,
+                self.execute_command(&command_id, command_info),
+            )
+            .await;
+            let command_duration = command_start_time.elapsed();
+
+            let mut result = SelfTestResultDTO::new(
+                command_info.id.clone(),
+                false,
+                command_duration,
+                None,
+                None,
             );
-            return Err(RunCommandError::CommandNotFound);
+
+            match run_result {
+                Err(_) => result.error = Some(format!("timed out after {command_duration:?}")),
+                Ok(Err(run_command_error)) => result.error = Some(format!("{run_command_error:?}")),
+                Ok(Ok(run_command_dto)) => {
+                    result.passed = run_command_dto.succeeded();
+                    result.exit_status = run_command_dto.exit_status;
+                    if !result.passed {
+                        result.error = Some(run_command_dto.command_output);
+                    }
+                }
+            }
+
+            results.push(result);
+        }
+
+        SelfTestReportDTO::new(results)
+    }
+}
+
+impl CommandsService for CommandsServiceImpl {
+    fn all_commands(&self, external_request: bool, principal: &Principal) -> Vec<CommandInfoDTO> {
+        self.state().all_commands(external_request, principal)
+    }
+
+    async fn run_command(
+        &self,
+        external_request: bool,
+        principal: &Principal,
+        command_id: CommandID,
+    ) -> Result<RunCommandDTO, RunCommandError> {
+        self.state()
+            .run_command(external_request, principal, command_id)
+            .await
+    }
+
+    fn command_history(
+        &self,
+        external_request: bool,
+        principal: &Principal,
+        command_id: CommandID,
+    ) -> Result<CommandHistoryDTO, RunCommandError> {
+        self.state()
+            .command_history(external_request, principal, command_id)
+    }
+
+    fn diff_previous_run(&self, run_command_dto: RunCommandDTO) -> RunCommandDTO {
+        self.state().diff_previous_run(run_command_dto)
+    }
+
+    fn commands_status(&self, external_request: bool, principal: &Principal) -> CommandsStatusDTO {
+        self.state().commands_status(external_request, principal)
+    }
+
+    async fn self_test(&self) -> SelfTestReportDTO {
+        self.state().self_test().await
+    }
+
+    fn start_schedules(&self) {
+        let mut schedule_tasks = self.schedule_tasks.lock().unwrap();
+
+        *schedule_tasks = Some(schedule::start_scheduled_commands(&self.state()));
+    }
+
+    fn reload(&self, configuration: Arc<config::Configuration>) -> anyhow::Result<()> {
+        let state = Arc::new(CommandsState::new(configuration, Some(&self.state()))?);
+
+        let mut schedule_tasks = self.schedule_tasks.lock().unwrap();
+
+        // dropping the previous tasks aborts schedules of the old state
+        if schedule_tasks.is_some() {
+            *schedule_tasks = Some(schedule::start_scheduled_commands(&state));
         }
 
-        let permit = self.acquire_semaphore().await?;
+        self.state.store(state);
 
-        Ok(self.internal_run_command(command_info, permit).await)
+        Ok(())
     }
 }
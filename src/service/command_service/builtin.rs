@@ -0,0 +1,558 @@
+use anyhow::Context;
+
+use serde::Serialize;
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::config;
+
+// Roots of the proc and sys filesystems, from command_configuration so
+// builtins can read host filesystems mounted elsewhere or fixture trees.
+#[derive(Clone)]
+pub struct BuiltinPaths {
+    pub proc_root: PathBuf,
+    pub sys_root: PathBuf,
+}
+
+impl From<&config::CommandConfiguration> for BuiltinPaths {
+    fn from(command_configuration: &config::CommandConfiguration) -> Self {
+        Self {
+            proc_root: command_configuration.proc_root.clone(),
+            sys_root: command_configuration.sys_root.clone(),
+        }
+    }
+}
+
+pub struct BuiltinOutput {
+    pub text: String,
+    pub parsed: serde_json::Value,
+}
+
+impl BuiltinOutput {
+    fn new(text: String, parsed: impl Serialize) -> anyhow::Result<Self> {
+        Ok(Self {
+            text,
+            parsed: serde_json::to_value(parsed).context("serde_json::to_value error")?,
+        })
+    }
+}
+
+pub const BUILTIN_NAMES: [&str; 5] = ["loadavg", "meminfo", "cpu_stat", "filesystem", "thermal"];
+
+pub fn run_builtin(
+    name: &str,
+    args: &[String],
+    builtin_paths: &BuiltinPaths,
+) -> anyhow::Result<BuiltinOutput> {
+    match name {
+        "loadavg" => loadavg(&builtin_paths.proc_root),
+        "meminfo" => meminfo(&builtin_paths.proc_root),
+        "cpu_stat" => cpu_stat(&builtin_paths.proc_root),
+        "filesystem" => filesystem(args),
+        "thermal" => thermal(&builtin_paths.sys_root),
+        _ => anyhow::bail!("unknown builtin command '{name}'"),
+    }
+}
+
+fn read_file(path: &Path) -> anyhow::Result<String> {
+    fs::read_to_string(path).with_context(|| format!("error reading '{}'", path.display()))
+}
+
+#[derive(Debug, Serialize)]
+struct LoadAverage {
+    load_1m: f64,
+    load_5m: f64,
+    load_15m: f64,
+    running_tasks: u64,
+    total_tasks: u64,
+}
+
+fn parse_loadavg(contents: &str) -> anyhow::Result<LoadAverage> {
+    let fields: Vec<&str> = contents.split_whitespace().collect();
+
+    let [load_1m, load_5m, load_15m, tasks, ..] = fields.as_slice() else {
+        anyhow::bail!("unexpected loadavg format '{contents}'");
+    };
+
+    let (running_tasks, total_tasks) = tasks
+        .split_once('/')
+        .with_context(|| format!("unexpected loadavg tasks format '{tasks}'"))?;
+
+    Ok(LoadAverage {
+        load_1m: load_1m.parse()?,
+        load_5m: load_5m.parse()?,
+        load_15m: load_15m.parse()?,
+        running_tasks: running_tasks.parse()?,
+        total_tasks: total_tasks.parse()?,
+    })
+}
+
+fn loadavg(proc_root: &Path) -> anyhow::Result<BuiltinOutput> {
+    let load_average = parse_loadavg(&read_file(&proc_root.join("loadavg"))?)?;
+
+    let text = format!(
+        "load average: {:.2}, {:.2}, {:.2}\ntasks: {} running, {} total\n",
+        load_average.load_1m,
+        load_average.load_5m,
+        load_average.load_15m,
+        load_average.running_tasks,
+        load_average.total_tasks,
+    );
+
+    BuiltinOutput::new(text, load_average)
+}
+
+// Values are in bytes where /proc/meminfo reports kB, otherwise unscaled.
+fn parse_meminfo(contents: &str) -> anyhow::Result<BTreeMap<String, u64>> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (key, value) = line
+                .split_once(':')
+                .with_context(|| format!("unexpected meminfo line '{line}'"))?;
+
+            let mut value_fields = value.split_whitespace();
+
+            let mut value: u64 = value_fields
+                .next()
+                .with_context(|| format!("missing meminfo value '{line}'"))?
+                .parse()?;
+
+            if value_fields.next() == Some("kB") {
+                value *= 1024;
+            }
+
+            Ok((key.trim().to_owned(), value))
+        })
+        .collect()
+}
+
+fn meminfo(proc_root: &Path) -> anyhow::Result<BuiltinOutput> {
+    let meminfo = parse_meminfo(&read_file(&proc_root.join("meminfo"))?)?;
+
+    let mut text = String::new();
+    for key in [
+        "MemTotal",
+        "MemFree",
+        "MemAvailable",
+        "Buffers",
+        "Cached",
+        "SwapTotal",
+        "SwapFree",
+    ] {
+        if let Some(value) = meminfo.get(key) {
+            writeln!(text, "{:<14}{}", format!("{key}:"), format_bytes(*value))?;
+        }
+    }
+
+    BuiltinOutput::new(text, meminfo)
+}
+
+#[derive(Debug, Default, Serialize)]
+struct CpuTimes {
+    name: String,
+    user: u64,
+    nice: u64,
+    system: u64,
+    idle: u64,
+    iowait: u64,
+    irq: u64,
+    softirq: u64,
+    steal: u64,
+    busy_percent: f64,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct CpuStat {
+    cpus: Vec<CpuTimes>,
+    context_switches: Option<u64>,
+    boot_time: Option<u64>,
+    processes: Option<u64>,
+    procs_running: Option<u64>,
+    procs_blocked: Option<u64>,
+}
+
+fn parse_cpu_times(name: &str, values: &[u64]) -> CpuTimes {
+    let value = |index: usize| values.get(index).copied().unwrap_or_default();
+
+    let mut cpu_times = CpuTimes {
+        name: name.to_owned(),
+        user: value(0),
+        nice: value(1),
+        system: value(2),
+        idle: value(3),
+        iowait: value(4),
+        irq: value(5),
+        softirq: value(6),
+        steal: value(7),
+        busy_percent: 0.0,
+    };
+
+    // guest time is already included in user and nice
+    let total: u64 = values.iter().take(8).sum();
+    let idle = cpu_times.idle + cpu_times.iowait;
+    if total > 0 {
+        cpu_times.busy_percent = ((total - idle) as f64 / total as f64) * 100.0;
+    }
+
+    cpu_times
+}
+
+fn parse_cpu_stat(contents: &str) -> anyhow::Result<CpuStat> {
+    let mut cpu_stat = CpuStat::default();
+
+    for line in contents.lines() {
+        let mut fields = line.split_whitespace();
+
+        let Some(key) = fields.next() else {
+            continue;
+        };
+
+        let values = fields
+            .map(|field| field.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("unexpected stat line '{line}'"))?;
+
+        let first_value = values.first().copied();
+
+        match key {
+            key if key.starts_with("cpu") => cpu_stat.cpus.push(parse_cpu_times(key, &values)),
+            "ctxt" => cpu_stat.context_switches = first_value,
+            "btime" => cpu_stat.boot_time = first_value,
+            "processes" => cpu_stat.processes = first_value,
+            "procs_running" => cpu_stat.procs_running = first_value,
+            "procs_blocked" => cpu_stat.procs_blocked = first_value,
+            _ => {}
+        }
+    }
+
+    Ok(cpu_stat)
+}
+
+fn cpu_stat(proc_root: &Path) -> anyhow::Result<BuiltinOutput> {
+    let cpu_stat = parse_cpu_stat(&read_file(&proc_root.join("stat"))?)?;
+
+    let mut text = format!(
+        "{:<8}{:>12}{:>12}{:>12}{:>12}{:>12}{:>8}\n",
+        "cpu", "user", "system", "idle", "iowait", "steal", "busy%"
+    );
+    for cpu in &cpu_stat.cpus {
+        writeln!(
+            text,
+            "{:<8}{:>12}{:>12}{:>12}{:>12}{:>12}{:>8.1}",
+            cpu.name, cpu.user, cpu.system, cpu.idle, cpu.iowait, cpu.steal, cpu.busy_percent
+        )?;
+    }
+    if let Some(procs_running) = cpu_stat.procs_running {
+        writeln!(text, "procs_running: {procs_running}")?;
+    }
+    if let Some(procs_blocked) = cpu_stat.procs_blocked {
+        writeln!(text, "procs_blocked: {procs_blocked}")?;
+    }
+
+    BuiltinOutput::new(text, cpu_stat)
+}
+
+#[derive(Debug, Serialize)]
+struct FilesystemUsage {
+    path: String,
+    size_bytes: u64,
+    used_bytes: u64,
+    available_bytes: u64,
+    used_percent: f64,
+}
+
+// statvfs field types are narrower than u64 on some targets
+#[allow(clippy::useless_conversion)]
+fn filesystem_usage(path: &str) -> anyhow::Result<FilesystemUsage> {
+    let statvfs = nix::sys::statvfs::statvfs(path)
+        .with_context(|| format!("statvfs error path = '{path}'"))?;
+
+    let fragment_size = u64::from(statvfs.fragment_size());
+    let size_bytes = u64::from(statvfs.blocks()) * fragment_size;
+    let free_bytes = u64::from(statvfs.blocks_free()) * fragment_size;
+    let available_bytes = u64::from(statvfs.blocks_available()) * fragment_size;
+    let used_bytes = size_bytes - free_bytes;
+
+    // same as df, reserved blocks are excluded
+    let usable_bytes = used_bytes + available_bytes;
+    let used_percent = if usable_bytes > 0 {
+        (used_bytes as f64 / usable_bytes as f64) * 100.0
+    } else {
+        0.0
+    };
+
+    Ok(FilesystemUsage {
+        path: path.to_owned(),
+        size_bytes,
+        used_bytes,
+        available_bytes,
+        used_percent,
+    })
+}
+
+fn filesystem(args: &[String]) -> anyhow::Result<BuiltinOutput> {
+    let paths = if args.is_empty() {
+        vec!["/".to_owned()]
+    } else {
+        args.to_vec()
+    };
+
+    let filesystems = paths
+        .iter()
+        .map(|path| filesystem_usage(path))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut text = format!(
+        "{:<24}{:>12}{:>12}{:>12}{:>8}\n",
+        "Path", "Size", "Used", "Avail", "Use%"
+    );
+    for filesystem in &filesystems {
+        writeln!(
+            text,
+            "{:<24}{:>12}{:>12}{:>12}{:>7.0}%",
+            filesystem.path,
+            format_bytes(filesystem.size_bytes),
+            format_bytes(filesystem.used_bytes),
+            format_bytes(filesystem.available_bytes),
+            filesystem.used_percent,
+        )?;
+    }
+
+    BuiltinOutput::new(text, filesystems)
+}
+
+#[derive(Debug, Serialize)]
+struct ThermalZone {
+    zone: String,
+    zone_type: String,
+    temp_celsius: f64,
+}
+
+fn thermal(sys_root: &Path) -> anyhow::Result<BuiltinOutput> {
+    let thermal_dir = sys_root.join("class").join("thermal");
+
+    let mut zone_paths = fs::read_dir(&thermal_dir)
+        .with_context(|| format!("error reading '{}'", thermal_dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|file_name| file_name.to_str())
+                .is_some_and(|file_name| file_name.starts_with("thermal_zone"))
+        })
+        .collect::<Vec<_>>();
+    zone_paths.sort();
+
+    let zones = zone_paths
+        .iter()
+        .map(|zone_path| {
+            let millidegrees: i64 = read_file(&zone_path.join("temp"))?
+                .trim()
+                .parse()
+                .with_context(|| format!("error parsing '{}/temp'", zone_path.display()))?;
+
+            Ok(ThermalZone {
+                zone: zone_path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                zone_type: read_file(&zone_path.join("type"))?.trim().to_owned(),
+                temp_celsius: millidegrees as f64 / 1000.0,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut text = String::new();
+    for zone in &zones {
+        writeln!(
+            text,
+            "{:<16}{:<24}{:>6.1} C",
+            zone.zone, zone.zone_type, zone.temp_celsius
+        )?;
+    }
+
+    BuiltinOutput::new(text, zones)
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A proc and sys tree under the temp dir, removed when dropped.
+    struct FixtureTree {
+        root: PathBuf,
+    }
+
+    impl FixtureTree {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir()
+                .join(format!("rust-axum-builtin-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            Self { root }
+        }
+
+        fn write(&self, path: &str, contents: &str) {
+            let path = self.root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        fn builtin_paths(&self) -> BuiltinPaths {
+            BuiltinPaths {
+                proc_root: self.root.join("proc"),
+                sys_root: self.root.join("sys"),
+            }
+        }
+    }
+
+    impl Drop for FixtureTree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn loadavg() {
+        let load_average = parse_loadavg("0.52 0.58 0.59 2/1203 12345\n").unwrap();
+
+        assert_eq!(load_average.load_1m, 0.52);
+        assert_eq!(load_average.load_5m, 0.58);
+        assert_eq!(load_average.load_15m, 0.59);
+        assert_eq!(load_average.running_tasks, 2);
+        assert_eq!(load_average.total_tasks, 1203);
+
+        assert!(parse_loadavg("0.52 0.58\n").is_err());
+        assert!(parse_loadavg("0.52 0.58 0.59 1203 12345\n").is_err());
+
+        let fixture_tree = FixtureTree::new("loadavg");
+        fixture_tree.write("proc/loadavg", "1.00 0.50 0.25 3/100 42\n");
+
+        let output = run_builtin("loadavg", &[], &fixture_tree.builtin_paths()).unwrap();
+
+        assert_eq!(
+            output.text,
+            "load average: 1.00, 0.50, 0.25\ntasks: 3 running, 100 total\n"
+        );
+        assert_eq!(output.parsed["total_tasks"], 100);
+    }
+
+    #[test]
+    fn meminfo() {
+        let fixture_tree = FixtureTree::new("meminfo");
+        fixture_tree.write(
+            "proc/meminfo",
+            "MemTotal:       16384000 kB\nMemFree:         1024 kB\nHugePages_Total:       4\n\n",
+        );
+
+        let meminfo =
+            parse_meminfo(&read_file(&fixture_tree.root.join("proc/meminfo")).unwrap()).unwrap();
+
+        assert_eq!(meminfo["MemTotal"], 16384000 * 1024);
+        assert_eq!(meminfo["MemFree"], 1024 * 1024);
+        assert_eq!(meminfo["HugePages_Total"], 4);
+
+        assert!(parse_meminfo("MemTotal 1024 kB\n").is_err());
+        assert!(parse_meminfo("MemTotal:\n").is_err());
+
+        let output = run_builtin("meminfo", &[], &fixture_tree.builtin_paths()).unwrap();
+
+        assert_eq!(
+            output.text,
+            "MemTotal:     15.6 GiB\nMemFree:      1.0 MiB\n"
+        );
+    }
+
+    #[test]
+    fn cpu_stat() {
+        let cpu_stat = parse_cpu_stat(
+            "cpu  100 0 100 700 100 0 0 0 0 0\n\
+             cpu0 50 0 50 350 50 0 0 0 0 0\n\
+             intr 12345 0 1\n\
+             ctxt 987654\n\
+             btime 1700000000\n\
+             processes 4321\n\
+             procs_running 2\n\
+             procs_blocked 1\n",
+        )
+        .unwrap();
+
+        assert_eq!(cpu_stat.cpus.len(), 2);
+        assert_eq!(cpu_stat.cpus[0].name, "cpu");
+        assert_eq!(cpu_stat.cpus[0].idle, 700);
+        assert_eq!(cpu_stat.cpus[0].busy_percent, 20.0);
+        assert_eq!(cpu_stat.cpus[1].name, "cpu0");
+        assert_eq!(cpu_stat.context_switches, Some(987654));
+        assert_eq!(cpu_stat.boot_time, Some(1700000000));
+        assert_eq!(cpu_stat.processes, Some(4321));
+        assert_eq!(cpu_stat.procs_running, Some(2));
+        assert_eq!(cpu_stat.procs_blocked, Some(1));
+
+        assert!(parse_cpu_stat("cpu 100 x\n").is_err());
+
+        let fixture_tree = FixtureTree::new("cpu_stat");
+        fixture_tree.write("proc/stat", "cpu  0 0 0 0 0 0 0 0\nprocs_running 1\n");
+
+        let output = run_builtin("cpu_stat", &[], &fixture_tree.builtin_paths()).unwrap();
+
+        assert!(output.text.ends_with("procs_running: 1\n"));
+        assert_eq!(output.parsed["cpus"][0]["busy_percent"], 0.0);
+    }
+
+    #[test]
+    fn thermal() {
+        let fixture_tree = FixtureTree::new("thermal");
+        fixture_tree.write("sys/class/thermal/thermal_zone1/temp", "45500\n");
+        fixture_tree.write("sys/class/thermal/thermal_zone1/type", "cpu-thermal\n");
+        fixture_tree.write("sys/class/thermal/thermal_zone0/temp", "-2000\n");
+        fixture_tree.write("sys/class/thermal/thermal_zone0/type", "acpitz\n");
+        fixture_tree.write("sys/class/thermal/cooling_device0/type", "Processor\n");
+
+        let output = run_builtin("thermal", &[], &fixture_tree.builtin_paths()).unwrap();
+
+        assert_eq!(
+            output.text,
+            "thermal_zone0   acpitz                    -2.0 C\n\
+             thermal_zone1   cpu-thermal               45.5 C\n"
+        );
+        assert_eq!(output.parsed[1]["zone_type"], "cpu-thermal");
+        assert_eq!(output.parsed[1]["temp_celsius"], 45.5);
+
+        fixture_tree.write("sys/class/thermal/thermal_zone2/temp", "hot\n");
+
+        assert!(run_builtin("thermal", &[], &fixture_tree.builtin_paths()).is_err());
+    }
+
+    #[test]
+    fn missing_files() {
+        let fixture_tree = FixtureTree::new("missing");
+
+        for name in ["loadavg", "meminfo", "cpu_stat", "thermal"] {
+            assert!(
+                run_builtin(name, &[], &fixture_tree.builtin_paths()).is_err(),
+                "{name}"
+            );
+        }
+    }
+}
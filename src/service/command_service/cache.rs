@@ -0,0 +1,53 @@
+use tokio::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tracing::debug;
+
+use super::{RunCommandDTO, RunCommandError};
+
+struct CachedResult {
+    completion_instant: Instant,
+    run_command_dto: RunCommandDTO,
+}
+
+pub struct CommandResultCache {
+    ttl: Duration,
+    cached_result: Mutex<Option<CachedResult>>,
+}
+
+impl CommandResultCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            cached_result: Mutex::new(None),
+        }
+    }
+
+    // The mutex is held while the command runs, so concurrent callers wait for
+    // the in-flight execution and are then served its result from the cache.
+    pub async fn get_or_run(
+        &self,
+        run: impl Future<Output = Result<RunCommandDTO, RunCommandError>>,
+    ) -> Result<RunCommandDTO, RunCommandError> {
+        let mut cached_result = self.cached_result.lock().await;
+
+        if let Some(cached_result) = cached_result.as_ref() {
+            let age = cached_result.completion_instant.elapsed();
+            if age < self.ttl {
+                debug!(?age, "returning cached result");
+                return Ok(cached_result.run_command_dto.cached_copy(age));
+            }
+        }
+
+        let run_command_dto = run.await?;
+
+        *cached_result = Some(CachedResult {
+            completion_instant: Instant::now(),
+            run_command_dto: run_command_dto.clone(),
+        });
+
+        Ok(run_command_dto)
+    }
+}
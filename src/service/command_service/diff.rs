@@ -0,0 +1,72 @@
+use serde::Serialize;
+
+use similar::TextDiff;
+
+use std::sync::{Arc, Mutex};
+
+use super::RunCommandDTO;
+
+#[derive(Debug)]
+pub struct RunSnapshot {
+    now: String,
+    command_output: String,
+}
+
+impl From<&RunCommandDTO> for RunSnapshot {
+    fn from(run_command_dto: &RunCommandDTO) -> Self {
+        Self {
+            now: run_command_dto.now.clone(),
+            command_output: run_command_dto.command_output.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CommandOutputDiffDTO {
+    previous_now: Option<String>,
+    current_now: String,
+    unified_diff: String,
+}
+
+impl CommandOutputDiffDTO {
+    pub fn unified_diff(&self) -> &str {
+        &self.unified_diff
+    }
+}
+
+// Output of the most recent execution of a command, including scheduled runs.
+// Cached copies are not recorded.
+#[derive(Default)]
+pub struct RecentRuns {
+    latest: Mutex<Option<Arc<RunSnapshot>>>,
+}
+
+impl RecentRuns {
+    // Records the run as the latest one and keeps the run it replaces with it,
+    // so it can be diffed later, e.g. as a cached copy after newer runs.
+    pub fn add_run(&self, run_command_dto: &mut RunCommandDTO) {
+        let snapshot = Arc::new(RunSnapshot::from(&*run_command_dto));
+
+        run_command_dto.previous_run = self.latest.lock().unwrap().replace(snapshot);
+    }
+}
+
+// Diff against the run before the given one, or against empty output if there
+// was none.
+pub fn diff_previous(run_command_dto: &RunCommandDTO) -> CommandOutputDiffDTO {
+    let previous = run_command_dto.previous_run.as_deref();
+
+    let previous_output = previous.map_or("", |previous| &previous.command_output);
+
+    let unified_diff = TextDiff::from_lines(previous_output, &run_command_dto.command_output)
+        .unified_diff()
+        .context_radius(3)
+        .header("previous", "current")
+        .to_string();
+
+    CommandOutputDiffDTO {
+        previous_now: previous.map(|previous| previous.now.clone()),
+        current_now: run_command_dto.now.clone(),
+        unified_diff,
+    }
+}
@@ -0,0 +1,184 @@
+use anyhow::Context;
+
+use nix::sys::resource::{Resource, setrlimit};
+
+use serde::{Deserialize, Serialize};
+
+use std::{
+    convert::Infallible,
+    ffi::OsString,
+    os::unix::process::CommandExt,
+    process::{Output, Stdio},
+};
+
+use tokio::process::Command;
+
+use crate::config::{CommandExecutionConfiguration, CommandResourceLimits, SandboxProfile};
+
+// Resource limits and sandboxing must be applied in the child before it execs
+// the configured command, which is not possible from safe code between fork and
+// exec.  Instead the server re-executes itself with this marker argument,
+// applies the limits and sandbox and then execs the configured command.  When
+// uid or gid are also configured the server binary must be executable by that
+// user.
+const LAUNCHER_ARG: &str = "__rust_axum_command_launcher";
+
+const SANDBOX_SETUP_ERROR_EXIT_CODE: i32 = 125;
+
+const SANDBOX_SETUP_ERROR_MESSAGE: &str = "rust-axum command launcher sandbox setup error";
+
+#[derive(Serialize)]
+struct LauncherSpecRef<'a> {
+    rlimits: &'a CommandResourceLimits,
+    sandbox_profile: Option<&'a SandboxProfile>,
+}
+
+#[derive(Deserialize)]
+struct LauncherSpec {
+    rlimits: CommandResourceLimits,
+    sandbox_profile: Option<SandboxProfile>,
+}
+
+pub fn build_command(
+    program: &str,
+    args: &[String],
+    execution: &CommandExecutionConfiguration,
+    sandbox_profile: Option<&SandboxProfile>,
+) -> std::io::Result<Command> {
+    let mut command = if execution.rlimits.is_empty() && sandbox_profile.is_none() {
+        Command::new(program)
+    } else {
+        let launcher_spec_json = serde_json::to_string(&LauncherSpecRef {
+            rlimits: &execution.rlimits,
+            sandbox_profile,
+        })?;
+
+        let mut command = Command::new(std::env::current_exe()?);
+        command
+            .arg(LAUNCHER_ARG)
+            .arg(launcher_spec_json)
+            .arg(program);
+        command
+    };
+
+    command.args(args).kill_on_drop(true).stdin(Stdio::null());
+
+    if execution.env_clear || !execution.env_inherit.is_empty() {
+        command.env_clear();
+        for name in &execution.env_inherit {
+            if let Some(value) = std::env::var_os(name) {
+                command.env(name, value);
+            }
+        }
+    }
+
+    command.envs(&execution.env);
+
+    if let Some(cwd) = &execution.cwd {
+        command.current_dir(cwd);
+    }
+
+    if let Some(gid) = execution.gid {
+        command.gid(gid);
+    }
+
+    if let Some(uid) = execution.uid {
+        command.uid(uid);
+    }
+
+    Ok(command)
+}
+
+// Returns the launcher's error message if it failed to set up the sandbox.
+pub fn sandbox_setup_error(output: &Output) -> Option<String> {
+    if output.status.code() != Some(SANDBOX_SETUP_ERROR_EXIT_CODE)
+        || !output
+            .stderr
+            .starts_with(SANDBOX_SETUP_ERROR_MESSAGE.as_bytes())
+    {
+        return None;
+    }
+
+    Some(
+        String::from_utf8_lossy(&output.stderr)
+            .trim_end()
+            .to_owned(),
+    )
+}
+
+// Must be called before any threads are started, unsharing namespaces
+// requires a single threaded process.
+pub fn exec_launcher_if_requested() {
+    let mut args = std::env::args_os().skip(1);
+
+    if args.next().is_none_or(|arg| arg != LAUNCHER_ARG) {
+        return;
+    }
+
+    // Only returns on error, stderr is captured as command output.
+    let Err(error) = run_launcher(args);
+    eprintln!("command launcher error: {error:#}");
+    std::process::exit(127);
+}
+
+fn run_launcher(mut args: impl Iterator<Item = OsString>) -> anyhow::Result<Infallible> {
+    let launcher_spec_json = args
+        .next()
+        .and_then(|arg| arg.into_string().ok())
+        .context("missing launcher spec argument")?;
+
+    let launcher_spec: LauncherSpec = serde_json::from_str(&launcher_spec_json)
+        .context("error parsing launcher spec argument")?;
+
+    let program = args.next().context("missing command argument")?;
+
+    apply_rlimits(&launcher_spec.rlimits)?;
+
+    if let Some(sandbox_profile) = &launcher_spec.sandbox_profile
+        && let Err(error) = apply_sandbox(sandbox_profile)
+    {
+        eprintln!("{SANDBOX_SETUP_ERROR_MESSAGE}: {error:#}");
+        std::process::exit(SANDBOX_SETUP_ERROR_EXIT_CODE);
+    }
+
+    let exec_error = std::process::Command::new(&program).args(args).exec();
+
+    Err(exec_error).with_context(|| format!("exec error program = {program:?}"))
+}
+
+fn apply_rlimits(rlimits: &CommandResourceLimits) -> anyhow::Result<()> {
+    // validation rejects limits this platform does not have
+    let resource_limits = [
+        (Resource::RLIMIT_CPU, rlimits.cpu_seconds),
+        (Resource::RLIMIT_NOFILE, rlimits.open_files),
+        #[cfg(not(any(target_os = "freebsd", target_os = "netbsd", target_os = "openbsd")))]
+        (Resource::RLIMIT_AS, rlimits.address_space_bytes),
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "openbsd"
+        ))]
+        (Resource::RLIMIT_NPROC, rlimits.processes),
+    ];
+
+    for (resource, limit) in resource_limits {
+        if let Some(limit) = limit {
+            setrlimit(resource, limit, limit)
+                .with_context(|| format!("setrlimit error resource = {resource:?}"))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn apply_sandbox(sandbox_profile: &SandboxProfile) -> anyhow::Result<()> {
+    super::sandbox::apply_sandbox(sandbox_profile)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn apply_sandbox(_sandbox_profile: &SandboxProfile) -> anyhow::Result<()> {
+    anyhow::bail!("command sandboxing is only supported on linux")
+}
@@ -0,0 +1,296 @@
+use anyhow::Context;
+
+use serde_json::{Map, Value, json};
+
+use crate::config::OutputParser;
+
+pub fn parse_output(parser: &OutputParser, stdout: &str) -> anyhow::Result<Value> {
+    match parser {
+        OutputParser::Vmstat => parse_vmstat(stdout),
+        OutputParser::Df => parse_df(stdout),
+        OutputParser::IpAddrJson => parse_ip_addr_json(stdout),
+        OutputParser::KeyValue => Ok(parse_key_value(stdout)),
+        OutputParser::TableWhitespace => parse_table_whitespace(stdout),
+        OutputParser::Json => serde_json::from_str(stdout).context("error parsing json"),
+    }
+}
+
+fn non_empty_lines(stdout: &str) -> impl Iterator<Item = &str> {
+    stdout.lines().filter(|line| !line.trim().is_empty())
+}
+
+// Numbers are converted to json numbers, everything else is kept as a string.
+fn field_value(field: &str) -> Value {
+    if let Ok(number) = field.parse::<i64>() {
+        return number.into();
+    }
+
+    match field.parse::<f64>() {
+        Ok(number) if number.is_finite() => number.into(),
+        _ => field.into(),
+    }
+}
+
+// Split a row into columns, the last column gets the remainder of the line.
+fn split_row(line: &str, num_columns: usize) -> Vec<&str> {
+    let mut fields = Vec::with_capacity(num_columns);
+    let mut rest = line.trim();
+
+    while fields.len() + 1 < num_columns {
+        let Some((field, remainder)) = rest.split_once(char::is_whitespace) else {
+            break;
+        };
+        fields.push(field);
+        rest = remainder.trim_start();
+    }
+
+    if !rest.is_empty() {
+        fields.push(rest);
+    }
+
+    fields
+}
+
+fn parse_rows<'a>(
+    columns: &[String],
+    rows: impl Iterator<Item = &'a str>,
+) -> anyhow::Result<Value> {
+    rows.map(|line| {
+        let fields = split_row(line, columns.len());
+
+        if fields.len() != columns.len() {
+            anyhow::bail!(
+                "expected {} columns got {} in line '{line}'",
+                columns.len(),
+                fields.len(),
+            );
+        }
+
+        Ok(columns
+            .iter()
+            .cloned()
+            .zip(fields.into_iter().map(field_value))
+            .collect::<Map<_, _>>()
+            .into())
+    })
+    .collect::<anyhow::Result<Vec<Value>>>()
+    .map(Value::Array)
+}
+
+fn parse_table_whitespace(stdout: &str) -> anyhow::Result<Value> {
+    let mut lines = non_empty_lines(stdout);
+
+    let columns: Vec<String> = lines
+        .next()
+        .context("missing header line")?
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect();
+
+    parse_rows(&columns, lines)
+}
+
+// vmstat prints a line of column groups before the column names.
+fn parse_vmstat(stdout: &str) -> anyhow::Result<Value> {
+    let mut lines = non_empty_lines(stdout);
+
+    lines.next().context("missing vmstat group header line")?;
+
+    let columns: Vec<String> = lines
+        .next()
+        .context("missing vmstat header line")?
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect();
+
+    parse_rows(&columns, lines)
+}
+
+// The df header ends with "Mounted on" so it cannot be split on whitespace.
+fn parse_df(stdout: &str) -> anyhow::Result<Value> {
+    let mut lines = non_empty_lines(stdout);
+
+    let header = lines.next().context("missing df header line")?;
+
+    let columns: Vec<String> = split_row(header, 6)
+        .into_iter()
+        .map(|column| {
+            column
+                .to_lowercase()
+                .replace('%', "_percent")
+                .replace(' ', "_")
+        })
+        .collect();
+
+    parse_rows(&columns, lines)
+}
+
+// Condense `ip -json addr` output to interface names, state and addresses.
+fn parse_ip_addr_json(stdout: &str) -> anyhow::Result<Value> {
+    let interfaces: Vec<Value> =
+        serde_json::from_str(stdout).context("error parsing ip addr json")?;
+
+    Ok(interfaces
+        .iter()
+        .map(|interface| {
+            let addresses: Vec<Value> = interface["addr_info"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|addr_info| {
+                    Some(format!(
+                        "{}/{}",
+                        addr_info["local"].as_str()?,
+                        addr_info["prefixlen"].as_u64()?,
+                    ))
+                })
+                .map(Value::from)
+                .collect();
+
+            json!({
+                "ifname": interface["ifname"],
+                "operstate": interface["operstate"],
+                "mtu": interface["mtu"],
+                "address": interface["address"],
+                "addresses": addresses,
+            })
+        })
+        .collect())
+}
+
+// Lines of "key: value" or "key=value", other lines are ignored.
+fn parse_key_value(stdout: &str) -> Value {
+    non_empty_lines(stdout)
+        .filter_map(|line| {
+            let (key, value) = line.split_once([':', '='])?;
+            let key = key.trim();
+            if key.is_empty() {
+                return None;
+            }
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            Some((key.to_owned(), field_value(value)))
+        })
+        .collect::<Map<_, _>>()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DF_H: &str = "\
+Filesystem      Size  Used Avail Use% Mounted on
+/dev/root        29G  5.1G   23G  19% /
+tmpfs           1.9G     0  1.9G   0% /dev/shm
+/dev/mmcblk0p1  510M   64M  447M  13% /boot/firmware
+//nas/share     1.8T  1.2T  600G  67% /mnt/nas share
+";
+
+    const VMSTAT: &str = "\
+procs -----------memory---------- ---swap-- -----io---- -system-- ------cpu-----
+ r  b   swpd   free   buff  cache   si   so    bi    bo   in   cs us sy id wa st
+ 1  0      0 3383628  63380 421556    0    0    12     3   45   80  1  0 99  0  0
+";
+
+    #[test]
+    fn df_headers() {
+        let parsed = parse_output(&OutputParser::Df, DF_H).unwrap();
+
+        let rows = parsed.as_array().unwrap();
+        assert_eq!(rows.len(), 4);
+
+        assert_eq!(
+            rows[0],
+            json!({
+                "filesystem": "/dev/root",
+                "size": "29G",
+                "used": "5.1G",
+                "avail": "23G",
+                "use_percent": "19%",
+                "mounted_on": "/",
+            })
+        );
+
+        // the last column keeps spaces in mount points
+        assert_eq!(rows[3]["mounted_on"], "/mnt/nas share");
+    }
+
+    #[test]
+    fn vmstat_header_lines() {
+        let parsed = parse_output(&OutputParser::Vmstat, VMSTAT).unwrap();
+
+        let rows = parsed.as_array().unwrap();
+        assert_eq!(rows.len(), 1);
+
+        let cases = [
+            ("r", json!(1)),
+            ("free", json!(3_383_628)),
+            ("cs", json!(80)),
+            ("id", json!(99)),
+            ("st", json!(0)),
+        ];
+
+        for (column, expected) in cases {
+            assert_eq!(rows[0][column], expected, "{column}");
+        }
+
+        // the group header line is not used as column names
+        assert!(rows[0].get("procs").is_none());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases = [
+            (OutputParser::Vmstat, "procs ---memory---\n"),
+            (OutputParser::Df, ""),
+            (OutputParser::TableWhitespace, "a b c\n1 2\n"),
+            (OutputParser::Json, "{"),
+            (OutputParser::IpAddrJson, "{}"),
+        ];
+
+        for (parser, stdout) in cases {
+            assert!(
+                parse_output(&parser, stdout).is_err(),
+                "{parser:?} {stdout:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn field_values() {
+        let cases = [
+            ("42", json!(42)),
+            ("-1", json!(-1)),
+            ("0.5", json!(0.5)),
+            ("NaN", json!("NaN")),
+            ("inf", json!("inf")),
+            ("19%", json!("19%")),
+        ];
+
+        for (field, expected) in cases {
+            assert_eq!(field_value(field), expected, "{field}");
+        }
+    }
+
+    #[test]
+    fn key_values() {
+        let parsed = parse_output(
+            &OutputParser::KeyValue,
+            "NAME=\"Debian GNU/Linux\"\nVERSION_ID=\"12\"\nuptime: 3.5\nno separator\n=empty key\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            parsed,
+            json!({
+                "NAME": "Debian GNU/Linux",
+                "VERSION_ID": 12,
+                "uptime": 3.5,
+            })
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use std::{
+    io,
+    process::{Output, Stdio},
+};
+
+use tokio::{
+    task::JoinSet,
+    time::{Duration, Instant},
+};
+
+use tracing::debug;
+
+use crate::config::{self, SandboxProfile, StepFailureMode};
+
+use super::execution;
+
+pub struct ProcessRun<'a> {
+    pub command: &'a str,
+    pub args: &'a [String],
+    pub output: io::Result<Output>,
+    pub duration: Duration,
+}
+
+impl ProcessRun<'_> {
+    pub fn exit_status(&self) -> Option<i32> {
+        self.output
+            .as_ref()
+            .ok()
+            .and_then(|output| output.status.code())
+    }
+
+    fn succeeded(&self) -> bool {
+        self.output
+            .as_ref()
+            .is_ok_and(|output| output.status.success())
+    }
+
+    pub fn stdout(&self) -> Option<&[u8]> {
+        self.output
+            .as_ref()
+            .ok()
+            .map(|output| output.stdout.as_slice())
+    }
+
+    pub fn combined_output(&self) -> String {
+        match &self.output {
+            Err(err) => {
+                format!("error running command {err}")
+            }
+            Ok(output) => {
+                let mut combined_output =
+                    String::with_capacity(output.stderr.len() + output.stdout.len());
+                combined_output.push_str(&String::from_utf8_lossy(&output.stderr));
+                combined_output.push_str(&String::from_utf8_lossy(&output.stdout));
+                combined_output
+            }
+        }
+    }
+}
+
+// Like bash pipefail, the rightmost failed process determines the exit status.
+pub fn overall_exit_status(process_runs: &[ProcessRun<'_>]) -> Option<i32> {
+    process_runs
+        .iter()
+        .rev()
+        .find(|process_run| !process_run.succeeded())
+        .or(process_runs.last())
+        .and_then(ProcessRun::exit_status)
+}
+
+pub async fn run_process<'a>(
+    command: &'a str,
+    args: &'a [String],
+    execution: &config::CommandExecutionConfiguration,
+    sandbox_profile: Option<&SandboxProfile>,
+) -> ProcessRun<'a> {
+    let start_time = Instant::now();
+    let output = match execution::build_command(command, args, execution, sandbox_profile) {
+        Ok(mut command) => command.output().await,
+        Err(err) => Err(err),
+    };
+
+    ProcessRun {
+        command,
+        args,
+        output,
+        duration: start_time.elapsed(),
+    }
+}
+
+pub async fn run_steps<'a>(
+    command_info: &'a config::CommandInfo,
+    sandbox_profile: Option<&SandboxProfile>,
+) -> Vec<ProcessRun<'a>> {
+    let mut process_runs = Vec::with_capacity(command_info.steps.len());
+
+    for step in &command_info.steps {
+        let process_run = run_process(
+            &step.command,
+            &step.args,
+            &command_info.execution,
+            sandbox_profile,
+        )
+        .await;
+
+        let succeeded = process_run.succeeded();
+
+        process_runs.push(process_run);
+
+        if !succeeded && matches!(command_info.step_failure_mode, StepFailureMode::FailFast) {
+            debug!(command_info.id, "step failed, skipping remaining steps");
+            break;
+        }
+    }
+
+    process_runs
+}
+
+// Every process is spawned before any is awaited, stdout of each process is
+// connected to stdin of the next with an OS pipe.
+pub async fn run_pipeline<'a>(
+    command_info: &'a config::CommandInfo,
+    sandbox_profile: Option<&SandboxProfile>,
+) -> Vec<ProcessRun<'a>> {
+    let start_time = Instant::now();
+
+    let mut children = Vec::with_capacity(command_info.steps.len());
+    let mut previous_stdout: Option<Stdio> = None;
+
+    for step in &command_info.steps {
+        let spawn_result = execution::build_command(
+            &step.command,
+            &step.args,
+            &command_info.execution,
+            sandbox_profile,
+        )
+        .and_then(|mut command| {
+            if let Some(stdin) = previous_stdout.take() {
+                command.stdin(stdin);
+            }
+            command
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+        });
+
+        let mut child = match spawn_result {
+            Ok(child) => child,
+            Err(err) => {
+                // children spawned so far are killed on drop
+                return vec![ProcessRun {
+                    command: &step.command,
+                    args: &step.args,
+                    output: Err(err),
+                    duration: start_time.elapsed(),
+                }];
+            }
+        };
+
+        if children.len() + 1 < command_info.steps.len() {
+            previous_stdout = child
+                .stdout
+                .take()
+                .and_then(|stdout| stdout.try_into().ok());
+        }
+
+        children.push(child);
+    }
+
+    // the JoinSet aborts its tasks on drop, killing any remaining children
+    let mut wait_tasks = JoinSet::new();
+    for (index, child) in children.into_iter().enumerate() {
+        wait_tasks.spawn(async move {
+            let output = child.wait_with_output().await;
+            (index, output, start_time.elapsed())
+        });
+    }
+
+    let mut indexed_outputs = Vec::with_capacity(wait_tasks.len());
+    while let Some(result) = wait_tasks.join_next().await {
+        match result {
+            Ok(indexed_output) => indexed_outputs.push(indexed_output),
+            Err(err) => debug!(?err, "pipeline wait task error"),
+        }
+    }
+
+    indexed_outputs.sort_by_key(|(index, _, _)| *index);
+
+    indexed_outputs
+        .into_iter()
+        .map(|(index, output, duration)| {
+            let step = &command_info.steps[index];
+            ProcessRun {
+                command: &step.command,
+                args: &step.args,
+                output,
+                duration,
+            }
+        })
+        .collect()
+}
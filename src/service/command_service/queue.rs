@@ -0,0 +1,103 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::{
+    sync::{Semaphore, SemaphorePermit},
+    time::Instant,
+};
+
+use tracing::warn;
+
+use super::CommandQueueStatusDTO;
+
+const QUEUE_METRICS_ORDERING: Ordering = Ordering::Relaxed;
+
+// A semaphore with a bounded FIFO queue of waiters in front of it.
+pub struct CommandQueue {
+    semaphore: Semaphore,
+    max_permits: usize,
+    max_queue_depth: Option<usize>,
+    queue_depth: AtomicUsize,
+    enqueued: AtomicUsize,
+    dequeued: AtomicUsize,
+}
+
+struct QueueEntry<'a> {
+    ticket: usize,
+    command_queue: &'a CommandQueue,
+}
+
+impl QueueEntry<'_> {
+    // 1-based position in the queue, assuming waiters leave in FIFO order.
+    fn queue_position(&self) -> usize {
+        self.ticket
+            .saturating_sub(self.command_queue.dequeued.load(QUEUE_METRICS_ORDERING))
+            + 1
+    }
+}
+
+impl Drop for QueueEntry<'_> {
+    fn drop(&mut self) {
+        self.command_queue
+            .queue_depth
+            .fetch_sub(1, QUEUE_METRICS_ORDERING);
+        self.command_queue
+            .dequeued
+            .fetch_add(1, QUEUE_METRICS_ORDERING);
+    }
+}
+
+impl CommandQueue {
+    pub fn new(max_permits: usize, max_queue_depth: Option<usize>) -> Self {
+        Self {
+            semaphore: Semaphore::new(max_permits),
+            max_permits,
+            max_queue_depth,
+            queue_depth: AtomicUsize::new(0),
+            enqueued: AtomicUsize::new(0),
+            dequeued: AtomicUsize::new(0),
+        }
+    }
+
+    // On failure returns the queue position the caller held when it gave up.
+    pub async fn acquire(&self, deadline: Instant) -> Result<SemaphorePermit<'_>, usize> {
+        if let Ok(permit) = self.semaphore.try_acquire() {
+            return Ok(permit);
+        }
+
+        let queue_depth = self.queue_depth.fetch_add(1, QUEUE_METRICS_ORDERING) + 1;
+
+        let queue_entry = QueueEntry {
+            ticket: self.enqueued.fetch_add(1, QUEUE_METRICS_ORDERING),
+            command_queue: self,
+        };
+
+        if self
+            .max_queue_depth
+            .is_some_and(|max_queue_depth| queue_depth > max_queue_depth)
+        {
+            warn!(queue_depth, "queue full");
+            return Err(queue_entry.queue_position());
+        }
+
+        match tokio::time::timeout_at(deadline, self.semaphore.acquire()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            Ok(Err(error)) => {
+                warn!(?error, "semaphore acquire error");
+                Err(queue_entry.queue_position())
+            }
+            Err(error) => {
+                warn!(?error, "semaphore acquire timeout error");
+                Err(queue_entry.queue_position())
+            }
+        }
+    }
+
+    pub fn status_dto(&self) -> CommandQueueStatusDTO {
+        CommandQueueStatusDTO::new(
+            self.max_permits,
+            self.max_permits - self.semaphore.available_permits(),
+            self.max_queue_depth,
+            self.queue_depth.load(QUEUE_METRICS_ORDERING),
+        )
+    }
+}
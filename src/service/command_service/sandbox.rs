@@ -0,0 +1,104 @@
+use anyhow::Context;
+
+use landlock::{
+    ABI, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus,
+    path_beneath_rules,
+};
+
+use nix::{
+    sched::{CloneFlags, unshare},
+    sys::prctl,
+    unistd::Uid,
+};
+
+use seccompiler::{SeccompAction, SeccompFilter, TargetArch};
+
+use std::{collections::BTreeMap, str::FromStr};
+
+use syscalls::Sysno;
+
+use crate::config::{SandboxFilesystem, SandboxProfile};
+
+const LANDLOCK_ABI: ABI = ABI::V5;
+
+// Must be called from a single threaded process, seccomp is applied last so
+// the other setup calls are not filtered.
+pub fn apply_sandbox(sandbox_profile: &SandboxProfile) -> anyhow::Result<()> {
+    if sandbox_profile.no_network {
+        unshare_network()?;
+    }
+
+    if sandbox_profile.no_new_privs {
+        prctl::set_no_new_privs().context("set_no_new_privs error")?;
+    }
+
+    if let Some(filesystem) = &sandbox_profile.filesystem {
+        restrict_filesystem(filesystem)?;
+    }
+
+    if !sandbox_profile.denied_syscalls.is_empty() {
+        apply_seccomp_filter(&sandbox_profile.denied_syscalls)?;
+    }
+
+    Ok(())
+}
+
+fn unshare_network() -> anyhow::Result<()> {
+    // Unprivileged processes need a new user namespace to create a network namespace.
+    let flags = if Uid::effective().is_root() {
+        CloneFlags::CLONE_NEWNET
+    } else {
+        CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNET
+    };
+
+    unshare(flags).with_context(|| format!("unshare error flags = {flags:?}"))
+}
+
+fn restrict_filesystem(filesystem: &SandboxFilesystem) -> anyhow::Result<()> {
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(LANDLOCK_ABI))?
+        .create()?
+        .add_rules(path_beneath_rules(
+            &filesystem.read_only,
+            AccessFs::from_read(LANDLOCK_ABI),
+        ))?
+        .add_rules(path_beneath_rules(
+            &filesystem.read_write,
+            AccessFs::from_all(LANDLOCK_ABI),
+        ))?
+        .restrict_self()
+        .context("landlock restrict_self error")?;
+
+    if status.ruleset == RulesetStatus::NotEnforced {
+        anyhow::bail!("landlock is not supported by the running kernel");
+    }
+
+    Ok(())
+}
+
+fn apply_seccomp_filter(denied_syscalls: &[String]) -> anyhow::Result<()> {
+    let rules = denied_syscalls
+        .iter()
+        .map(|name| {
+            let sysno =
+                Sysno::from_str(name).map_err(|_| anyhow::anyhow!("unknown syscall '{name}'"))?;
+            Ok((i64::from(sysno.id()), Vec::new()))
+        })
+        .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+
+    let target_arch = TargetArch::try_from(std::env::consts::ARCH)
+        .context("seccomp is not supported on this architecture")?;
+
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Allow,
+        SeccompAction::Errno(nix::libc::EPERM as u32),
+        target_arch,
+    )
+    .context("SeccompFilter::new error")?;
+
+    let bpf_program: seccompiler::BpfProgram =
+        filter.try_into().context("seccomp filter compile error")?;
+
+    seccompiler::apply_filter(&bpf_program).context("seccomp apply_filter error")
+}
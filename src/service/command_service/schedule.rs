@@ -0,0 +1,133 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use tokio::{task::JoinSet, time::Duration};
+
+use tracing::{debug, instrument, warn};
+
+use crate::config;
+
+use super::{CommandHistoryEntryDTO, CommandID, CommandsState};
+
+pub enum ScheduleTrigger {
+    Interval(Duration),
+    Cron(Box<croner::Cron>),
+}
+
+impl ScheduleTrigger {
+    fn from_config(schedule: &config::CommandSchedule) -> Option<Self> {
+        match (schedule.interval, &schedule.cron) {
+            (Some(interval), None) => Some(Self::Interval(interval)),
+            (None, Some(cron)) => Some(Self::Cron(Box::new(cron.clone()))),
+            _ => None,
+        }
+    }
+
+    fn next_run_delay(&self) -> Option<Duration> {
+        match self {
+            Self::Interval(interval) => Some(*interval),
+            Self::Cron(cron) => {
+                let now = jiff::Zoned::now();
+                let next_run = cron
+                    .find_next_occurrence(&now, false)
+                    .inspect_err(|error| warn!(?error, "find_next_occurrence error"))
+                    .ok()?;
+                next_run.duration_since(&now).try_into().ok()
+            }
+        }
+    }
+}
+
+impl fmt::Display for ScheduleTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Interval(interval) => {
+                write!(
+                    f,
+                    "every {}",
+                    humantime_serde::re::humantime::format_duration(*interval)
+                )
+            }
+            Self::Cron(cron) => write!(f, "cron {cron}"),
+        }
+    }
+}
+
+pub struct CommandHistory {
+    pub trigger: ScheduleTrigger,
+    history_size: usize,
+    entries: Mutex<VecDeque<CommandHistoryEntryDTO>>,
+}
+
+impl CommandHistory {
+    pub fn new(command_info: &config::CommandInfo) -> Option<Self> {
+        let schedule = command_info.schedule.as_ref()?;
+
+        let Some(trigger) = ScheduleTrigger::from_config(schedule) else {
+            warn!(
+                command_info.id,
+                "schedule must set exactly one of interval or cron, ignoring schedule"
+            );
+            return None;
+        };
+
+        Some(Self {
+            trigger,
+            history_size: schedule.history_size,
+            entries: Mutex::new(VecDeque::with_capacity(schedule.history_size)),
+        })
+    }
+
+    fn add_entry(&self, entry: CommandHistoryEntryDTO) {
+        let mut entries = self.entries.lock().unwrap();
+
+        entries.push_front(entry);
+        entries.truncate(self.history_size);
+    }
+
+    // Newest entry first.
+    pub fn entries(&self) -> Vec<CommandHistoryEntryDTO> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+// Schedules are aborted when the returned `JoinSet` is dropped.
+pub fn start_scheduled_commands(commands_state: &Arc<CommandsState>) -> JoinSet<()> {
+    let mut schedule_tasks = JoinSet::new();
+
+    for command_id in commands_state.id_to_command_history.keys() {
+        schedule_tasks.spawn(run_schedule(Arc::clone(commands_state), command_id.clone()));
+    }
+
+    schedule_tasks
+}
+
+#[instrument(name = "schedule", skip(commands_state))]
+async fn run_schedule(commands_state: Arc<CommandsState>, command_id: CommandID) {
+    let Some(command_info) = commands_state.command_info(&command_id) else {
+        return;
+    };
+    let command_history = &commands_state.id_to_command_history[&command_id];
+
+    debug!(trigger = %command_history.trigger, "begin run_schedule");
+
+    loop {
+        let Some(delay) = command_history.trigger.next_run_delay() else {
+            warn!("no next run time, ending run_schedule");
+            return;
+        };
+
+        tokio::time::sleep(delay).await;
+
+        match commands_state
+            .execute_command(&command_id, command_info)
+            .await
+        {
+            Ok(run_command_dto) => command_history.add_entry(run_command_dto.into()),
+            Err(error) => warn!(?error, "scheduled run error"),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    collections::HashSet,
+    env,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use tokio::sync::Semaphore;
+
+use tracing::warn;
+
+use crate::config;
+
+use super::builtin;
+
+fn valid_command_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+// Commands without a '/' are looked up in PATH like `Command` does.
+fn resolve_command_path(command: &str) -> Option<PathBuf> {
+    if command.contains('/') {
+        return Some(PathBuf::from(command));
+    }
+
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(command))
+        .find(|path| path.is_file())
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+// Missing or non-executable paths are only warned about, the path may be
+// valid when the command is run.
+fn check_command_path(command_id: &str, command: &str) {
+    match resolve_command_path(command) {
+        Some(path) if is_executable(&path) => {}
+        Some(path) => warn!(
+            command_id,
+            ?path,
+            "command path is missing or not executable"
+        ),
+        None => warn!(command_id, command, "command not found in PATH"),
+    }
+}
+
+fn validate_command(
+    command_configuration: &config::CommandConfiguration,
+    command_info: &config::CommandInfo,
+) -> Vec<String> {
+    let id = &command_info.id;
+    let mut errors = Vec::new();
+
+    if !valid_command_id(id) {
+        errors.push(format!(
+            "command id '{id}' must only contain ascii letters, digits, '_', '-' and '.'"
+        ));
+    }
+
+    match command_info.kind {
+        config::CommandKind::External => {
+            if command_info.command.is_empty() {
+                errors.push(format!("command '{id}' has an empty command"));
+            } else {
+                check_command_path(id, &command_info.command);
+            }
+        }
+        config::CommandKind::Builtin => {
+            if !builtin::BUILTIN_NAMES.contains(&command_info.command.as_str()) {
+                errors.push(format!(
+                    "command '{id}' has unknown builtin '{}', expected one of {:?}",
+                    command_info.command,
+                    builtin::BUILTIN_NAMES,
+                ));
+            }
+        }
+        config::CommandKind::Steps | config::CommandKind::Pipeline => {
+            if command_info.steps.is_empty() {
+                errors.push(format!("command '{id}' has no steps"));
+            }
+            for step in &command_info.steps {
+                check_command_path(id, &step.command);
+            }
+        }
+    }
+
+    if command_info.max_concurrent == Some(0) {
+        errors.push(format!("command '{id}' has max_concurrent = 0"));
+    } else if command_info
+        .max_concurrent
+        .is_some_and(|max_concurrent| max_concurrent > Semaphore::MAX_PERMITS)
+    {
+        errors.push(format!(
+            "command '{id}' max_concurrent must be at most {}",
+            Semaphore::MAX_PERMITS
+        ));
+    }
+
+    if let Some(schedule) = &command_info.schedule
+        && schedule.interval.is_some() == schedule.cron.is_some()
+    {
+        errors.push(format!(
+            "command '{id}' schedule must have exactly one of interval or cron"
+        ));
+    }
+
+    if let Some(schedule) = &command_info.schedule
+        && schedule.interval.is_some_and(|interval| interval.is_zero())
+    {
+        errors.push(format!("command '{id}' schedule has interval = 0"));
+    }
+
+    // resource limits setrlimit does not have on this platform, e.g. macOS
+    // has no RLIMIT_NPROC
+    #[cfg(any(target_os = "freebsd", target_os = "netbsd", target_os = "openbsd"))]
+    if command_info.execution.rlimits.address_space_bytes.is_some() {
+        errors.push(format!(
+            "command '{id}' rlimits.address_space_bytes is not supported on this platform"
+        ));
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )))]
+    if command_info.execution.rlimits.processes.is_some() {
+        errors.push(format!(
+            "command '{id}' rlimits.processes is not supported on this platform"
+        ));
+    }
+
+    if let Some(sandbox_profile) = &command_info.execution.sandbox_profile
+        && !command_configuration
+            .sandbox_profiles
+            .contains_key(sandbox_profile)
+    {
+        errors.push(format!(
+            "command '{id}' has unknown sandbox_profile '{sandbox_profile}'"
+        ));
+    }
+
+    errors
+}
+
+pub fn validate_command_configuration(
+    command_configuration: &config::CommandConfiguration,
+) -> anyhow::Result<()> {
+    let mut errors = Vec::new();
+
+    if command_configuration.max_concurrent_commands == 0 {
+        errors.push("max_concurrent_commands must be greater than 0".to_owned());
+    } else if command_configuration.max_concurrent_commands > Semaphore::MAX_PERMITS {
+        errors.push(format!(
+            "max_concurrent_commands must be at most {}",
+            Semaphore::MAX_PERMITS
+        ));
+    }
+
+    let mut command_ids = HashSet::new();
+
+    for command_info in &command_configuration.commands {
+        if !command_ids.insert(&command_info.id) {
+            errors.push(format!("duplicate command id '{}'", command_info.id));
+        }
+
+        errors.extend(validate_command(command_configuration, command_info));
+    }
+
+    if !errors.is_empty() {
+        anyhow::bail!("invalid command configuration:\n{}", errors.join("\n"));
+    }
+
+    Ok(())
+}
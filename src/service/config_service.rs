@@ -0,0 +1,112 @@
+use serde::Serialize;
+
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    config::{self, ConfigSources, SharedConfiguration},
+    utils::time::current_timestamp_string,
+};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ReloadResultDTO {
+    time: String,
+    succeeded: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EffectiveConfigurationDTO {
+    // none when the server was not started from a config file
+    config_file: Option<String>,
+    overlay_files: Vec<String>,
+    // --set values are not shown, they may contain secrets
+    overrides: usize,
+    loaded_time: String,
+    last_reload: Option<ReloadResultDTO>,
+    // changed settings still running with the values shown in configuration
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pending_restart: Vec<&'static str>,
+    configuration: Arc<config::Configuration>,
+}
+
+#[trait_variant::make(Send)]
+pub trait ConfigService: Send + Sync + 'static {
+    // A successful reload returns the changed settings that need a restart.
+    fn record_reload(&self, result: &anyhow::Result<Vec<&'static str>>);
+
+    fn effective_configuration(&self) -> EffectiveConfigurationDTO;
+}
+
+pub fn new_config_service(
+    shared_configuration: SharedConfiguration,
+    config_sources: Option<ConfigSources>,
+) -> Arc<impl ConfigService> {
+    ConfigServiceImpl::new(shared_configuration, config_sources)
+}
+
+struct ConfigStatus {
+    loaded_time: String,
+    last_reload: Option<ReloadResultDTO>,
+    pending_restart: Vec<&'static str>,
+}
+
+struct ConfigServiceImpl {
+    shared_configuration: SharedConfiguration,
+    config_sources: Option<ConfigSources>,
+    status: Mutex<ConfigStatus>,
+}
+
+impl ConfigServiceImpl {
+    fn new(
+        shared_configuration: SharedConfiguration,
+        config_sources: Option<ConfigSources>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            shared_configuration,
+            config_sources,
+            status: Mutex::new(ConfigStatus {
+                loaded_time: current_timestamp_string(),
+                last_reload: None,
+                pending_restart: Vec::new(),
+            }),
+        })
+    }
+}
+
+impl ConfigService for ConfigServiceImpl {
+    fn record_reload(&self, result: &anyhow::Result<Vec<&'static str>>) {
+        let time = current_timestamp_string();
+
+        let mut status = self.status.lock().unwrap();
+
+        if let Ok(restart_required) = result {
+            status.loaded_time.clone_from(&time);
+            status.pending_restart.clone_from(restart_required);
+        }
+
+        status.last_reload = Some(ReloadResultDTO {
+            time,
+            succeeded: result.is_ok(),
+            error: result.as_ref().err().map(|error| format!("{error:#}")),
+        });
+    }
+
+    fn effective_configuration(&self) -> EffectiveConfigurationDTO {
+        let status = self.status.lock().unwrap();
+
+        let config_sources = self.config_sources.as_ref();
+
+        EffectiveConfigurationDTO {
+            config_file: config_sources.map(|config_sources| config_sources.config_file.clone()),
+            overlay_files: config_sources
+                .map(|config_sources| config_sources.overlay_files.clone())
+                .unwrap_or_default(),
+            overrides: config_sources.map_or(0, |config_sources| config_sources.overrides.len()),
+            loaded_time: status.loaded_time.clone(),
+            last_reload: status.last_reload.clone(),
+            pending_restart: status.pending_restart.clone(),
+            configuration: self.shared_configuration.current(),
+        }
+    }
+}
@@ -9,6 +9,7 @@ use serde::Serialize;
 
 use std::{
     collections::BTreeMap,
+    net::SocketAddr,
     sync::{Arc, atomic::AtomicUsize},
     time::SystemTime,
 };
@@ -19,14 +20,25 @@ const CONNECTION_METRICS_ORDERING: std::sync::atomic::Ordering =
     std::sync::atomic::Ordering::Relaxed;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Ord, PartialOrd)]
-pub struct ConnectionID(usize);
+pub struct ConnectionID(pub(crate) usize);
 
 impl ConnectionID {
+    pub fn new(id: usize) -> Self {
+        Self(id)
+    }
+
     pub fn as_usize(&self) -> usize {
         self.0
     }
 }
 
+// Per connection info available to handlers with the `ConnectInfo` extractor.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientConnectInfo {
+    pub connection_id: ConnectionID,
+    pub remote_addr: SocketAddr,
+}
+
 #[derive(Debug)]
 pub enum ConnectionCounterMetricName {
     Errors,
@@ -61,22 +73,31 @@ impl ConnectionInfo {
     }
 }
 
+// The tracker a ConnectionGuard reports to, so ConnectionTrackerService
+// implementations outside this crate can create guards.
+pub trait ConnectionGuardTracker: Send + Sync + 'static {
+    fn increment_counter_metric(&self, name: ConnectionCounterMetricName);
+
+    // Called when the guard is dropped, must not block.
+    fn connection_closed(self: Arc<Self>, id: ConnectionID);
+}
+
 pub struct ConnectionGuard {
     pub id: ConnectionID,
     num_requests: Arc<AtomicUsize>,
-    connection_tracker_service: Arc<ConnectionTrackerServiceImpl>,
+    connection_tracker: Arc<dyn ConnectionGuardTracker>,
 }
 
 impl ConnectionGuard {
-    fn new(
+    pub fn new(
         id: ConnectionID,
         num_requests: Arc<AtomicUsize>,
-        connection_tracker_service: Arc<ConnectionTrackerServiceImpl>,
+        connection_tracker: Arc<dyn ConnectionGuardTracker>,
     ) -> Self {
         Self {
             id,
             num_requests,
-            connection_tracker_service,
+            connection_tracker,
         }
     }
 
@@ -89,19 +110,13 @@ impl ConnectionGuard {
     }
 
     pub fn increment_counter_metric(&self, name: ConnectionCounterMetricName) {
-        self.connection_tracker_service
-            .increment_counter_metric(name);
+        self.connection_tracker.increment_counter_metric(name);
     }
 }
 
 impl Drop for ConnectionGuard {
     fn drop(&mut self) {
-        let id = self.id;
-        let connection_tracker_service = Arc::clone(&self.connection_tracker_service);
-
-        tokio::spawn(async move {
-            connection_tracker_service.remove_connection(id).await;
-        });
+        Arc::clone(&self.connection_tracker).connection_closed(self.id);
     }
 }
 
@@ -155,10 +170,18 @@ impl ConnectionTrackerServiceImpl {
             open_connections: state.open_connections().cloned().collect(),
         }
     }
+}
 
+impl ConnectionGuardTracker for ConnectionTrackerServiceImpl {
     fn increment_counter_metric(&self, name: ConnectionCounterMetricName) {
         self.counter_metrics.increment(name);
     }
+
+    fn connection_closed(self: Arc<Self>, id: ConnectionID) {
+        tokio::spawn(async move {
+            self.remove_connection(id).await;
+        });
+    }
 }
 
 impl ConnectionTrackerService for ConnectionTrackerServiceImpl {
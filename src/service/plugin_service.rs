@@ -0,0 +1,102 @@
+use axum::Router;
+
+use serde::Serialize;
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use crate::plugin::Plugin;
+
+#[derive(Debug, Serialize)]
+pub struct HealthDTO {
+    healthy: bool,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    failed_checks: BTreeMap<&'static str, String>,
+}
+
+impl HealthDTO {
+    pub fn healthy(&self) -> bool {
+        self.healthy
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PluginStatusDTO {
+    name: &'static str,
+    healthy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    health_check_error: Option<String>,
+    metrics: BTreeMap<&'static str, usize>,
+}
+
+#[trait_variant::make(Send)]
+pub trait PluginService: Send + Sync + 'static {
+    fn plugin_names(&self) -> Vec<&'static str>;
+
+    // Routes added to the builder and by plugins.
+    fn routes(&self) -> Router;
+
+    fn health(&self) -> HealthDTO;
+
+    fn plugin_status(&self) -> Vec<PluginStatusDTO>;
+}
+
+pub fn new_plugin_service<C: 'static, T: 'static>(
+    plugins: Vec<Arc<dyn Plugin<C, T>>>,
+    routes: Router,
+) -> Arc<impl PluginService> {
+    PluginServiceImpl::new(plugins, routes)
+}
+
+struct PluginServiceImpl<C, T> {
+    plugins: Vec<Arc<dyn Plugin<C, T>>>,
+    routes: Router,
+}
+
+impl<C: 'static, T: 'static> PluginServiceImpl<C, T> {
+    fn new(plugins: Vec<Arc<dyn Plugin<C, T>>>, routes: Router) -> Arc<Self> {
+        Arc::new(Self { plugins, routes })
+    }
+}
+
+impl<C: 'static, T: 'static> PluginService for PluginServiceImpl<C, T> {
+    fn plugin_names(&self) -> Vec<&'static str> {
+        self.plugins.iter().map(|plugin| plugin.name()).collect()
+    }
+
+    fn routes(&self) -> Router {
+        self.routes.clone()
+    }
+
+    fn health(&self) -> HealthDTO {
+        let failed_checks: BTreeMap<&'static str, String> = self
+            .plugins
+            .iter()
+            .filter_map(|plugin| {
+                plugin
+                    .health_check()
+                    .err()
+                    .map(|error| (plugin.name(), error))
+            })
+            .collect();
+
+        HealthDTO {
+            healthy: failed_checks.is_empty(),
+            failed_checks,
+        }
+    }
+
+    fn plugin_status(&self) -> Vec<PluginStatusDTO> {
+        self.plugins
+            .iter()
+            .map(|plugin| {
+                let health_check_error = plugin.health_check().err();
+                PluginStatusDTO {
+                    name: plugin.name(),
+                    healthy: health_check_error.is_none(),
+                    health_check_error,
+                    metrics: plugin.metrics(),
+                }
+            })
+            .collect()
+    }
+}
@@ -7,11 +7,15 @@ use serde::Serialize;
 
 use std::collections::BTreeMap;
 
-use crate::service::connection_service::ConnectionID;
+use crate::utils::forwarded::ClientInfo;
 
 #[derive(Debug, Serialize)]
 struct RequestFieldsDTO {
     connection_id: usize,
+    remote_addr: String,
+    client_ip: Option<String>,
+    scheme: String,
+    host: Option<String>,
     method: String,
     version: &'static str,
     original_uri: String,
@@ -45,7 +49,7 @@ fn build_request_headers(request: &Request<Body>) -> BTreeMap<String, String> {
 }
 
 pub fn request_info(
-    connection_id: ConnectionID,
+    client_info: ClientInfo,
     original_uri: Uri,
     request: Request<Body>,
 ) -> RequestInfoDTO {
@@ -60,7 +64,11 @@ pub fn request_info(
 
     RequestInfoDTO {
         request_fields: RequestFieldsDTO {
-            connection_id: connection_id.as_usize(),
+            connection_id: client_info.client_connect_info.connection_id.as_usize(),
+            remote_addr: client_info.client_connect_info.remote_addr.to_string(),
+            client_ip: client_info.client_ip.map(|client_ip| client_ip.to_string()),
+            scheme: client_info.scheme,
+            host: client_info.host,
             method: request.method().as_str().to_owned(),
             version,
             original_uri: original_uri.to_string(),
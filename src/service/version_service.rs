@@ -1,7 +1,23 @@
+use serde::Serialize;
+
 use std::collections::BTreeMap;
 
 pub type VersionInfoMap = BTreeMap<&'static str, &'static str>;
 
+#[derive(Debug, Serialize)]
+pub struct VersionInfoDTO {
+    #[serde(flatten)]
+    version_info: VersionInfoMap,
+    plugins: Vec<&'static str>,
+}
+
+pub fn version_info_dto(plugins: Vec<&'static str>) -> VersionInfoDTO {
+    VersionInfoDTO {
+        version_info: verison_info(),
+        plugins,
+    }
+}
+
 pub fn verison_info() -> VersionInfoMap {
     let mut map = VersionInfoMap::default();
 
@@ -1,2 +1,4 @@
+pub mod forwarded;
+pub mod network_policy;
 pub mod request;
 pub mod time;
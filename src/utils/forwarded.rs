@@ -0,0 +1,458 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderName, header},
+    middleware::Next,
+    response::Response,
+};
+
+use std::net::IpAddr;
+
+use crate::{
+    config::{ForwardedHeader, NetworkPolicyConfiguration, SharedConfiguration},
+    service::connection_service::ClientConnectInfo,
+    utils::network_policy,
+};
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+// The client of a request as seen through trusted proxies, inserted as a
+// request extension by `resolve_client_info`.
+#[derive(Clone, Debug)]
+pub struct ClientInfo {
+    pub client_connect_info: ClientConnectInfo,
+    // none when a trusted proxy did not forward a readable client address
+    pub client_ip: Option<IpAddr>,
+    // whether client_ip is in the configured internal networks
+    pub internal: bool,
+    pub scheme: String,
+    pub host: Option<String>,
+}
+
+// One proxy hop from a Forwarded element or an X-Forwarded-For entry.
+#[derive(Debug, Default)]
+struct ForwardedHop {
+    for_ip: Option<IpAddr>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+// Node of a Forwarded "for" parameter, e.g. `192.0.2.1`, `"[2001:db8::1]:4711"`.
+fn parse_forwarded_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Some(bracketed) = node.strip_prefix('[') {
+        return bracketed.split_once(']')?.0.parse().ok();
+    }
+
+    // bare IPv6 addresses contain ':' so try without stripping a port first
+    node.parse()
+        .ok()
+        .or_else(|| node.split_once(':')?.0.parse().ok())
+}
+
+fn parse_proto(proto: &str) -> Option<String> {
+    let proto = proto.trim().trim_matches('"').to_ascii_lowercase();
+    matches!(proto.as_str(), "http" | "https").then_some(proto)
+}
+
+fn parse_host(host: &str) -> Option<String> {
+    let host = host.trim().trim_matches('"');
+    (!host.is_empty()).then(|| host.to_owned())
+}
+
+fn header_values(
+    request_headers: &HeaderMap,
+    name: impl header::AsHeaderName,
+) -> impl Iterator<Item = &str> {
+    request_headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+}
+
+// RFC 7239 elements, nearest hop last.
+fn forwarded_hops(request_headers: &HeaderMap) -> Vec<ForwardedHop> {
+    header_values(request_headers, header::FORWARDED)
+        .map(|element| {
+            let mut hop = ForwardedHop::default();
+            for pair in element.split(';') {
+                let Some((key, value)) = pair.split_once('=') else {
+                    continue;
+                };
+                match key.trim().to_ascii_lowercase().as_str() {
+                    "for" => hop.for_ip = parse_forwarded_node(value),
+                    "proto" => hop.proto = parse_proto(value),
+                    "host" => hop.host = parse_host(value),
+                    _ => {}
+                }
+            }
+            hop
+        })
+        .collect()
+}
+
+// X-Forwarded-For entries, nearest hop last. Proto and host are taken from the
+// last X-Forwarded-Proto and X-Forwarded-Host values, which the nearest proxy
+// sets.
+fn x_forwarded_hops(request_headers: &HeaderMap) -> Vec<ForwardedHop> {
+    let proto = header_values(request_headers, X_FORWARDED_PROTO)
+        .last()
+        .and_then(parse_proto);
+
+    let host = header_values(request_headers, X_FORWARDED_HOST)
+        .last()
+        .and_then(parse_host);
+
+    let mut hops: Vec<ForwardedHop> = header_values(request_headers, X_FORWARDED_FOR)
+        .map(|node| ForwardedHop {
+            for_ip: parse_forwarded_node(node),
+            ..Default::default()
+        })
+        .collect();
+
+    if proto.is_some() || host.is_some() {
+        match hops.last_mut() {
+            Some(hop) => {
+                hop.proto = proto;
+                hop.host = host;
+            }
+            None => hops.push(ForwardedHop {
+                for_ip: None,
+                proto,
+                host,
+            }),
+        }
+    }
+
+    hops
+}
+
+// Forwarding headers are only used when the peer is a trusted proxy.
+pub fn resolve(
+    network_policy: &NetworkPolicyConfiguration,
+    client_connect_info: ClientConnectInfo,
+    request_headers: &HeaderMap,
+) -> ClientInfo {
+    let peer_ip = client_connect_info.remote_addr.ip().to_canonical();
+
+    let mut client_info = ClientInfo {
+        client_connect_info,
+        client_ip: Some(peer_ip),
+        internal: false,
+        scheme: "http".to_owned(),
+        host: request_headers
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .and_then(parse_host),
+    };
+
+    if network_policy::is_trusted_proxy(network_policy, peer_ip) {
+        apply_forwarded_hops(network_policy, &mut client_info, request_headers);
+    }
+
+    client_info.internal = client_info
+        .client_ip
+        .is_some_and(|client_ip| network_policy::is_internal(network_policy, client_ip));
+
+    client_info
+}
+
+// The client is the nearest address that is not itself a trusted proxy, or
+// the farthest one when all are. A missing header or an obfuscated or unknown
+// node leaves the client unknown, so it is never taken for a proxy.
+fn apply_forwarded_hops(
+    network_policy: &NetworkPolicyConfiguration,
+    client_info: &mut ClientInfo,
+    request_headers: &HeaderMap,
+) {
+    let hops = match network_policy.forwarded_header {
+        ForwardedHeader::XForwardedFor => x_forwarded_hops(request_headers),
+        ForwardedHeader::Forwarded => forwarded_hops(request_headers),
+    };
+
+    client_info.client_ip = None;
+
+    for hop in hops.into_iter().rev() {
+        if let Some(proto) = hop.proto {
+            client_info.scheme = proto;
+        }
+        if hop.host.is_some() {
+            client_info.host = hop.host;
+        }
+
+        let Some(for_ip) = hop.for_ip else {
+            client_info.client_ip = None;
+            break;
+        };
+
+        let for_ip = for_ip.to_canonical();
+        client_info.client_ip = Some(for_ip);
+        if !network_policy::is_trusted_proxy(network_policy, for_ip) {
+            break;
+        }
+    }
+}
+
+// Inserts the `ClientInfo` extension for requests with connection info.
+pub async fn resolve_client_info(
+    State(shared_configuration): State<SharedConfiguration>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if let Some(ConnectInfo(client_connect_info)) = request
+        .extensions()
+        .get::<ConnectInfo<ClientConnectInfo>>()
+        .copied()
+    {
+        let client_info = resolve(
+            &shared_configuration
+                .current()
+                .server_configuration
+                .network_policy,
+            client_connect_info,
+            request.headers(),
+        );
+        request.extensions_mut().insert(client_info);
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    use crate::service::connection_service::ConnectionID;
+
+    fn network_policy(forwarded_header: ForwardedHeader) -> NetworkPolicyConfiguration {
+        NetworkPolicyConfiguration {
+            internal_networks: vec!["10.0.0.0/8".parse().unwrap()],
+            trusted_proxies: vec![
+                "127.0.0.1/32".parse().unwrap(),
+                "192.0.2.0/24".parse().unwrap(),
+            ],
+            forwarded_header,
+        }
+    }
+
+    fn resolve_from(
+        network_policy: &NetworkPolicyConfiguration,
+        peer_addr: &str,
+        headers: &[(&'static str, &str)],
+    ) -> ClientInfo {
+        let mut request_headers = HeaderMap::new();
+        for (name, value) in headers {
+            request_headers.append(*name, HeaderValue::from_str(value).unwrap());
+        }
+
+        let client_connect_info = ClientConnectInfo {
+            connection_id: ConnectionID(0),
+            remote_addr: peer_addr.parse().unwrap(),
+        };
+
+        resolve(network_policy, client_connect_info, &request_headers)
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn untrusted_peer_ignores_headers() {
+        let network_policy = network_policy(ForwardedHeader::XForwardedFor);
+
+        let client_info = resolve_from(
+            &network_policy,
+            "203.0.113.7:4711",
+            &[
+                ("x-forwarded-for", "10.0.0.1"),
+                ("x-forwarded-proto", "https"),
+                ("x-forwarded-host", "spoofed.example"),
+                ("forwarded", "for=10.0.0.1"),
+                ("host", "server.example"),
+            ],
+        );
+
+        assert_eq!(client_info.client_ip, ip("203.0.113.7"));
+        assert!(!client_info.internal);
+        assert_eq!(client_info.scheme, "http");
+        assert_eq!(client_info.host.as_deref(), Some("server.example"));
+    }
+
+    #[test]
+    fn internal_peer_without_proxy() {
+        let network_policy = network_policy(ForwardedHeader::XForwardedFor);
+
+        let client_info = resolve_from(&network_policy, "10.1.2.3:4711", &[]);
+
+        assert_eq!(client_info.client_ip, ip("10.1.2.3"));
+        assert!(client_info.internal);
+    }
+
+    #[test]
+    fn trusted_chain() {
+        let network_policy = network_policy(ForwardedHeader::XForwardedFor);
+
+        // client spoofs an internal address in front of the real chain
+        let client_info = resolve_from(
+            &network_policy,
+            "127.0.0.1:4711",
+            &[
+                ("x-forwarded-for", "10.0.0.1, 203.0.113.7"),
+                ("x-forwarded-for", "192.0.2.10"),
+                ("x-forwarded-proto", "https"),
+                ("x-forwarded-host", "public.example"),
+            ],
+        );
+
+        assert_eq!(client_info.client_ip, ip("203.0.113.7"));
+        assert!(!client_info.internal);
+        assert_eq!(client_info.scheme, "https");
+        assert_eq!(client_info.host.as_deref(), Some("public.example"));
+
+        let client_info = resolve_from(
+            &network_policy,
+            "127.0.0.1:4711",
+            &[("x-forwarded-for", "10.0.0.1, 192.0.2.10")],
+        );
+
+        assert_eq!(client_info.client_ip, ip("10.0.0.1"));
+        assert!(client_info.internal);
+    }
+
+    #[test]
+    fn all_hops_trusted() {
+        let network_policy = network_policy(ForwardedHeader::XForwardedFor);
+
+        let client_info = resolve_from(
+            &network_policy,
+            "127.0.0.1:4711",
+            &[("x-forwarded-for", "192.0.2.20, 192.0.2.10")],
+        );
+
+        assert_eq!(client_info.client_ip, ip("192.0.2.20"));
+    }
+
+    #[test]
+    fn ipv4_mapped_ipv6() {
+        let network_policy = network_policy(ForwardedHeader::XForwardedFor);
+
+        let client_info = resolve_from(
+            &network_policy,
+            "[::ffff:127.0.0.1]:4711",
+            &[("x-forwarded-for", "::ffff:10.0.0.1")],
+        );
+
+        assert_eq!(client_info.client_ip, ip("10.0.0.1"));
+        assert!(client_info.internal);
+
+        let client_info = resolve_from(&network_policy, "[::ffff:10.0.0.1]:4711", &[]);
+
+        assert_eq!(client_info.client_ip, ip("10.0.0.1"));
+        assert!(client_info.internal);
+    }
+
+    #[test]
+    fn missing_header_from_trusted_peer() {
+        let network_policy = network_policy(ForwardedHeader::XForwardedFor);
+
+        let client_info = resolve_from(&network_policy, "127.0.0.1:4711", &[]);
+
+        assert_eq!(client_info.client_ip, None);
+        assert!(!client_info.internal);
+    }
+
+    #[test]
+    fn unreadable_nodes() {
+        let forwarded_policy = network_policy(ForwardedHeader::Forwarded);
+
+        for forwarded in [
+            "for=unknown",
+            "for=_hidden",
+            "for=garbage",
+            "for=10.0.0.1, for=unknown",
+            "proto=https",
+        ] {
+            let client_info = resolve_from(
+                &forwarded_policy,
+                "127.0.0.1:4711",
+                &[("forwarded", forwarded)],
+            );
+
+            assert_eq!(client_info.client_ip, None, "{forwarded}");
+            assert!(!client_info.internal, "{forwarded}");
+        }
+
+        let network_policy = network_policy(ForwardedHeader::XForwardedFor);
+
+        let client_info = resolve_from(
+            &network_policy,
+            "127.0.0.1:4711",
+            &[("x-forwarded-for", "unknown")],
+        );
+
+        assert_eq!(client_info.client_ip, None);
+        assert!(!client_info.internal);
+    }
+
+    #[test]
+    fn forwarded_nodes() {
+        let network_policy = network_policy(ForwardedHeader::Forwarded);
+
+        let client_info = resolve_from(
+            &network_policy,
+            "127.0.0.1:4711",
+            &[(
+                "forwarded",
+                r#"for="[2001:db8::1]:4711";proto=https;host=public.example, for=192.0.2.10:80"#,
+            )],
+        );
+
+        assert_eq!(client_info.client_ip, ip("2001:db8::1"));
+        assert_eq!(client_info.scheme, "https");
+        assert_eq!(client_info.host.as_deref(), Some("public.example"));
+    }
+
+    #[test]
+    fn only_configured_header() {
+        let headers = [
+            ("forwarded", "for=10.0.0.1"),
+            ("x-forwarded-for", "203.0.113.7"),
+        ];
+
+        let client_info = resolve_from(
+            &network_policy(ForwardedHeader::XForwardedFor),
+            "127.0.0.1:4711",
+            &headers,
+        );
+
+        assert_eq!(client_info.client_ip, ip("203.0.113.7"));
+        assert!(!client_info.internal);
+
+        let client_info = resolve_from(
+            &network_policy(ForwardedHeader::Forwarded),
+            "127.0.0.1:4711",
+            &headers,
+        );
+
+        assert_eq!(client_info.client_ip, ip("10.0.0.1"));
+
+        // a client supplied Forwarded header is ignored when the proxy sets
+        // X-Forwarded-For
+        let client_info = resolve_from(
+            &network_policy(ForwardedHeader::XForwardedFor),
+            "127.0.0.1:4711",
+            &[("forwarded", "for=10.0.0.1")],
+        );
+
+        assert_eq!(client_info.client_ip, None);
+        assert!(!client_info.internal);
+    }
+}
@@ -0,0 +1,92 @@
+use std::net::IpAddr;
+
+use crate::config::NetworkPolicyConfiguration;
+
+fn contains(networks: &[ipnet::IpNet], ip: IpAddr) -> bool {
+    networks
+        .iter()
+        .any(|network| network.contains(&ip.to_canonical()))
+}
+
+// Requests from internal networks may see internal_only commands.
+pub fn is_internal(network_policy: &NetworkPolicyConfiguration, client_ip: IpAddr) -> bool {
+    contains(&network_policy.internal_networks, client_ip)
+}
+
+// Only trusted proxies may set forwarding headers.
+pub fn is_trusted_proxy(network_policy: &NetworkPolicyConfiguration, peer_ip: IpAddr) -> bool {
+    contains(&network_policy.trusted_proxies, peer_ip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network_policy() -> NetworkPolicyConfiguration {
+        NetworkPolicyConfiguration {
+            internal_networks: vec!["10.0.0.0/8".parse().unwrap(), "fc00::/7".parse().unwrap()],
+            trusted_proxies: vec!["127.0.0.1/32".parse().unwrap(), "::1/128".parse().unwrap()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn internal_networks() {
+        let network_policy = network_policy();
+
+        assert!(is_internal(&network_policy, "10.1.2.3".parse().unwrap()));
+        assert!(is_internal(&network_policy, "fd00::1".parse().unwrap()));
+        assert!(!is_internal(&network_policy, "192.0.2.1".parse().unwrap()));
+        assert!(!is_internal(
+            &network_policy,
+            "2001:db8::1".parse().unwrap()
+        ));
+        assert!(!is_internal(&network_policy, "127.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn trusted_proxies() {
+        let network_policy = network_policy();
+
+        assert!(is_trusted_proxy(
+            &network_policy,
+            "127.0.0.1".parse().unwrap()
+        ));
+        assert!(is_trusted_proxy(&network_policy, "::1".parse().unwrap()));
+        assert!(!is_trusted_proxy(
+            &network_policy,
+            "127.0.0.2".parse().unwrap()
+        ));
+        assert!(!is_trusted_proxy(
+            &network_policy,
+            "10.1.2.3".parse().unwrap()
+        ));
+    }
+
+    #[test]
+    fn ipv4_mapped_ipv6() {
+        let network_policy = network_policy();
+
+        assert!(is_internal(
+            &network_policy,
+            "::ffff:10.1.2.3".parse().unwrap()
+        ));
+        assert!(is_trusted_proxy(
+            &network_policy,
+            "::ffff:127.0.0.1".parse().unwrap()
+        ));
+    }
+
+    #[test]
+    fn default_policy() {
+        let network_policy = NetworkPolicyConfiguration::default();
+
+        assert!(is_internal(&network_policy, "127.0.0.1".parse().unwrap()));
+        assert!(is_internal(&network_policy, "::1".parse().unwrap()));
+        assert!(!is_internal(&network_policy, "10.1.2.3".parse().unwrap()));
+        assert!(!is_trusted_proxy(
+            &network_policy,
+            "127.0.0.1".parse().unwrap()
+        ));
+    }
+}
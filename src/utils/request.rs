@@ -1,12 +1,28 @@
-use axum::http::Request;
+use axum::http::{HeaderName, Request};
 
-use tower_http::request_id::{MakeRequestId, RequestId};
+use tower_http::{
+    request_id::{MakeRequestId, RequestId},
+    trace::MakeSpan,
+};
+
+use tracing::Span;
 
 use std::sync::{
     Arc,
     atomic::{AtomicU64, Ordering},
 };
 
+use super::forwarded::ClientInfo;
+
+const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+// Drops a client supplied x-request-id, so the server sets its own and
+// clients cannot choose the request ids logged and audited.
+pub fn remove_request_id<B>(mut request: Request<B>) -> Request<B> {
+    request.headers_mut().remove(X_REQUEST_ID);
+    request
+}
+
 // A `MakeRequestId` that increments an atomic counter
 #[derive(Clone, Default)]
 pub struct CounterRequestId {
@@ -25,3 +41,27 @@ impl MakeRequestId for CounterRequestId {
         Some(RequestId::new(request_id))
     }
 }
+
+// A `MakeSpan` like `DefaultMakeSpan` with headers that also records the
+// resolved client, so it must run after `forwarded::resolve_client_info`.
+#[derive(Clone, Default)]
+pub struct ClientMakeSpan;
+
+impl<B> MakeSpan<B> for ClientMakeSpan {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        let client_info = request.extensions().get::<ClientInfo>();
+
+        tracing::debug_span!(
+            "request",
+            method = %request.method(),
+            uri = %request.uri(),
+            version = ?request.version(),
+            client_ip = client_info
+                .and_then(|client_info| client_info.client_ip)
+                .map(tracing::field::display),
+            scheme = client_info.map(|client_info| client_info.scheme.as_str()),
+            host = client_info.and_then(|client_info| client_info.host.as_deref()),
+            headers = ?request.headers(),
+        )
+    }
+}